@@ -0,0 +1,144 @@
+use failure::Fail;
+use std::fmt;
+
+// x86-64 implements 48 bits of virtual address space.  Bits 47 to 63 of a
+// canonical address are all copies of bit 47.
+const VIRTUAL_ADDRESS_BITS: u32 = 48;
+
+/// An address in the memory read by a `MemReader`.
+///
+/// Arithmetic on an `Address` is checked and fails instead of wrapping.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address(pub u64);
+
+/// Why address arithmetic or validation failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddressError {
+    /// Offsetting `addr` by `offset` over or underflowed.
+    Overflow { addr: u64, offset: i128 },
+    /// The address can't be a user or kernel address on x86-64.
+    NonCanonical(u64),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::Overflow { addr, offset } if *offset < 0 => {
+                write!(f, "0x{:x} - 0x{:x} underflows", addr, -offset)
+            }
+            AddressError::Overflow { addr, offset } => {
+                write!(f, "0x{:x} + 0x{:x} overflows", addr, offset)
+            }
+            AddressError::NonCanonical(addr) => write!(f, "0x{:x} is not canonical", addr),
+        }
+    }
+}
+
+impl Fail for AddressError {}
+
+impl Address {
+    /// Offset the address by a signed `offset`.
+    pub fn offset(self, offset: i64) -> Result<Address, AddressError> {
+        let addr = match offset < 0 {
+            true => self.0.checked_sub(offset.unsigned_abs()),
+            false => self.0.checked_add(offset as u64),
+        };
+        addr.map(Address).ok_or(AddressError::Overflow {
+            addr: self.0,
+            offset: offset as i128,
+        })
+    }
+
+    /// Advance the address by `len` bytes.
+    pub fn advance(self, len: u64) -> Result<Address, AddressError> {
+        self.0
+            .checked_add(len)
+            .map(Address)
+            .ok_or(AddressError::Overflow {
+                addr: self.0,
+                offset: len as i128,
+            })
+    }
+
+    /// The distance from `base` up to the address, or `None` if the address
+    /// is below `base`.
+    pub fn offset_from(self, base: Address) -> Option<u64> {
+        self.0.checked_sub(base.0)
+    }
+
+    /// Returns true if the address is canonical on x86-64.
+    pub fn is_canonical(self) -> bool {
+        let high = (self.0 as i64) >> (VIRTUAL_ADDRESS_BITS - 1);
+        high == 0 || high == -1
+    }
+
+    /// Returns the address if it is canonical.
+    pub fn canonical(self) -> Result<Address, AddressError> {
+        match self.is_canonical() {
+            true => Ok(self),
+            false => Err(AddressError::NonCanonical(self.0)),
+        }
+    }
+}
+
+impl From<u64> for Address {
+    fn from(addr: u64) -> Self {
+        Address(addr)
+    }
+}
+
+impl From<Address> for u64 {
+    fn from(addr: Address) -> Self {
+        addr.0
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}", self.0)
+    }
+}
+
+impl fmt::LowerHex for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset() {
+        assert_eq!(Address(0x1000).offset(-0x10), Ok(Address(0xff0)));
+        assert_eq!(Address(0x1000).offset(0x10), Ok(Address(0x1010)));
+        assert_eq!(
+            Address(0x10).offset(-0x20),
+            Err(AddressError::Overflow {
+                addr: 0x10,
+                offset: -0x20
+            })
+        );
+        assert_eq!(
+            Address(0x1).offset(i64::MIN).map_err(|e| e.to_string()),
+            Err("0x1 - 0x8000000000000000 underflows".to_string())
+        );
+        assert!(Address(u64::MAX).offset(1).is_err());
+        assert!(Address(u64::MAX).advance(1).is_err());
+        assert_eq!(Address(0x1000).advance(0x10), Ok(Address(0x1010)));
+    }
+
+    #[test]
+    fn canonical() {
+        assert!(Address(0x0).is_canonical());
+        assert!(Address(0x7fff_ffff_ffff).is_canonical());
+        assert!(Address(0xffff_8000_0000_0000).is_canonical());
+        assert!(!Address(0x8000_0000_0000).is_canonical());
+        assert_eq!(
+            Address(0x4141_4141_4141_4141).canonical(),
+            Err(AddressError::NonCanonical(0x4141_4141_4141_4141))
+        );
+        assert_eq!(Address(0x1000).to_string(), "0x1000");
+    }
+}
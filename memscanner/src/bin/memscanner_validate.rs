@@ -0,0 +1,72 @@
+//! Check the signatures of a set of configs against a memory snapshot.
+//!
+//! Usage: memscanner_validate <snapshot> <config>...
+//!
+//! Prints a report for every config and exits with a non-zero status if any
+//! signature is not healthy.  Signatures that no longer match are followed
+//! by the closest near misses as a hint for updating them.
+
+use failure::{format_err, Error};
+use memscanner::validate::validate;
+use memscanner::{Snapshot, TypeConfig};
+use std::fs::File;
+use std::process::exit;
+
+// How many bytes a near miss may differ by, and how many are shown.
+const MAX_MISMATCHES: u32 = 4;
+const MAX_NEAR_MISSES: usize = 3;
+
+fn run() -> Result<bool, Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        return Err(format_err!(
+            "usage: memscanner_validate <snapshot> <config>..."
+        ));
+    }
+
+    let snapshot =
+        Snapshot::new(&mut File::open(&args[0])?).map_err(|e| format_err!("{}: {}", args[0], e))?;
+    let mut configs = vec![];
+    for path in &args[1..] {
+        let config =
+            TypeConfig::new(&mut File::open(path)?).map_err(|e| format_err!("{}: {}", path, e))?;
+        configs.push((path.clone(), config));
+    }
+
+    let reports = validate(
+        &snapshot,
+        snapshot.start_addr(),
+        snapshot.end_addr(),
+        &configs,
+    );
+    for (report, (_, config)) in reports.iter().zip(&configs) {
+        println!("{}", report);
+        if matches!(&report.matches, Some(m) if m.is_empty()) {
+            let near_misses = config
+                .signature
+                .fuzzy_matches(
+                    &snapshot,
+                    snapshot.start_addr(),
+                    snapshot.end_addr(),
+                    report.alternative,
+                    MAX_MISMATCHES,
+                )
+                .unwrap_or_default();
+            for near_miss in near_misses.iter().take(MAX_NEAR_MISSES) {
+                println!("    near miss at {}", near_miss);
+            }
+        }
+    }
+    Ok(reports.iter().all(|r| r.is_healthy()))
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use super::signature::{ResolveError, Signature};
+use super::MemReader;
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+// Modules without a PE or ELF identity are hashed this many bytes at a time
+// from up to `HASH_SAMPLES` places spread across the module.
+const HASH_LEN: u64 = 0x1000;
+const HASH_SAMPLES: u64 = 16;
+
+// ELF note type of the GNU build id.
+const NT_GNU_BUILD_ID: u32 = 3;
+
+// Build ids are usually 20 bytes.  Anything much longer is corrupt.
+const MAX_BUILD_ID_LEN: u64 = 64;
+
+/// A `ResolveCache` shared between the resolvers of several `TypeConfig`s.
+pub type SharedResolveCache = Arc<Mutex<ResolveCache>>;
+
+/// Signature resolutions remembered across runs.
+///
+/// Only the part of a signature that is relative to the scanned module is
+/// cached.  Addresses are stored as offsets from the start of the scanned
+/// range so they survive the module being loaded at a different address, and
+/// any `ptr()`, `ptr32()` or `module("name")` ops are replayed on every
+/// resolve.  All entries are dropped as soon as the module's fingerprint
+/// changes, so a cache should only be used for a single module.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ResolveCache {
+    /// Identifies the module the entries were resolved in.
+    pub fingerprint: String,
+    /// Entries keyed by signature text.
+    pub entries: HashMap<String, CacheEntry>,
+
+    // The range whose fingerprint has been checked this run.
+    #[serde(skip)]
+    checked: Option<(u64, u64)>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// The module relative part of a resolved signature.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CacheEntry {
+    /// The alternative that resolved.
+    pub alternative: usize,
+    /// The offset reached by the alternative's ops before its first `ptr()`,
+    /// `ptr32()` or `module("name")`.
+    pub offset: i64,
+}
+
+impl ResolveCache {
+    /// Read a json5 cache.
+    pub fn new(reader: &mut impl Read) -> Result<ResolveCache, Error> {
+        let mut buffer = String::new();
+        reader.read_to_string(&mut buffer)?;
+
+        Ok(json5::from_str(&buffer)?)
+    }
+
+    /// Write the cache as json5.  The output can be read back with `new`.
+    pub fn write(&mut self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.write_all(json5::to_string(self)?.as_bytes())?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Wrap the cache so it can be handed to several `TypeConfig`s.
+    pub fn shared(self) -> SharedResolveCache {
+        Arc::new(Mutex::new(self))
+    }
+
+    /// Returns true if entries were added or dropped since the cache was
+    /// read or last written.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Resolve `signature`, using the cached result if the module between
+    /// `start_addr` and `end_addr` is the one the cache was built for.
+    pub fn resolve(
+        &mut self,
+        signature: &Signature,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+    ) -> Result<u64, ResolveError> {
+        self.check_fingerprint(mem, start_addr, end_addr);
+
+        let key = signature.to_string();
+        if let Some(entry) = self.entries.get(&key) {
+            let prefix = start_addr.wrapping_add(entry.offset as u64);
+            if let Some(result) =
+                signature.resume(mem, start_addr, end_addr, entry.alternative, prefix)
+            {
+                return result;
+            }
+        }
+
+        let (alternative, prefix, addr) = signature.resolve_split(mem, start_addr, end_addr)?;
+        // Nothing is saved by caching a chain that starts with a pointer or
+        // another module.
+        if signature.module_relative_len(alternative) > 0 {
+            let entry = CacheEntry {
+                alternative,
+                offset: prefix.wrapping_sub(start_addr) as i64,
+            };
+            self.entries.insert(key, entry);
+            self.dirty = true;
+        }
+        Ok(addr)
+    }
+
+    fn check_fingerprint(&mut self, mem: &dyn MemReader, start_addr: u64, end_addr: u64) {
+        if self.checked == Some((start_addr, end_addr)) {
+            return;
+        }
+        let fingerprint = fingerprint(mem, start_addr, end_addr);
+        if fingerprint != self.fingerprint {
+            self.fingerprint = fingerprint;
+            self.entries.clear();
+            self.dirty = true;
+        }
+        self.checked = Some((start_addr, end_addr));
+    }
+}
+
+/// Identify the module between `start_addr` and `end_addr`.
+///
+/// PE images are identified by their link timestamp and image size, ELF
+/// images by their GNU build id.  Anything else falls back to the size of
+/// the range and a hash of a page from each sixteenth of it, so changes
+/// elsewhere in a large module can go unnoticed.
+pub fn fingerprint(mem: &dyn MemReader, start_addr: u64, end_addr: u64) -> String {
+    if let Some(f) = pe_fingerprint(mem, start_addr) {
+        return f;
+    }
+    if let Some(f) = elf_fingerprint(mem, start_addr) {
+        return f;
+    }
+
+    let size = end_addr.saturating_sub(start_addr);
+    let step = (size / HASH_SAMPLES).max(HASH_LEN);
+    let mut buf = vec![0x0; HASH_LEN as usize];
+    let mut hash = FNV_OFFSET;
+    let mut offset = 0;
+    while offset < size {
+        let len = (size - offset).min(HASH_LEN) as usize;
+        let read = mem.read(&mut buf, start_addr + offset, len);
+        hash = fnv1a(hash, &buf[..read]);
+        offset += step;
+    }
+    format!("hash:{:x}:{:016x}", size, hash)
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn pe_fingerprint(mem: &dyn MemReader, base: u64) -> Option<String> {
+    if mem.read_u16(base)? != u16::from_le_bytes(*b"MZ") {
+        return None;
+    }
+    let pe = base.checked_add(mem.read_u32(base.checked_add(0x3c)?)? as u64)?;
+    if mem.read_u32(pe)? != u32::from_le_bytes(*b"PE\0\0") {
+        return None;
+    }
+    let timestamp = mem.read_u32(pe.checked_add(8)?)?;
+    // SizeOfImage is at the same offset in PE32 and PE32+ optional headers.
+    let image_size = mem.read_u32(pe.checked_add(24 + 56)?)?;
+    Some(format!("pe:{:08x}:{:x}", timestamp, image_size))
+}
+
+fn elf_fingerprint(mem: &dyn MemReader, base: u64) -> Option<String> {
+    // Only 64 bit images are supported.
+    if mem.read_u32(base)? != u32::from_le_bytes(*b"\x7fELF")
+        || mem.read_u8(base.checked_add(4)?)? != 2
+    {
+        return None;
+    }
+    let phoff = mem.read_u64(base.checked_add(0x20)?)?;
+    let phentsize = mem.read_u16(base.checked_add(0x36)?)? as u64;
+    let phnum = mem.read_u16(base.checked_add(0x38)?)? as u64;
+
+    for i in 0..phnum {
+        let ph = base.checked_add(phoff)?.checked_add(i * phentsize)?;
+        // PT_NOTE
+        if mem.read_u32(ph)? != 4 {
+            continue;
+        }
+        // Position independent images have addresses relative to the base.
+        let vaddr = mem.read_u64(ph.checked_add(16)?)?;
+        let mut note = if vaddr < base {
+            base.checked_add(vaddr)?
+        } else {
+            vaddr
+        };
+        let end = note.checked_add(mem.read_u64(ph.checked_add(32)?)?)?;
+
+        while note.checked_add(12)? <= end {
+            let name = note.checked_add(12)?;
+            let namesz = mem.read_u32(note)? as u64;
+            let descsz = mem.read_u32(note.checked_add(4)?)? as u64;
+            let note_type = mem.read_u32(note.checked_add(8)?)?;
+            let desc = name.checked_add(align4(namesz))?;
+            if note_type == NT_GNU_BUILD_ID && namesz == 4 && mem.read_u32(name)? == 0x00554e47 {
+                if descsz > MAX_BUILD_ID_LEN {
+                    return None;
+                }
+                let mut id = vec![0x0; descsz as usize];
+                if mem.read(&mut id, desc, descsz as usize) != id.len() {
+                    return None;
+                }
+                let id: Vec<String> = id.iter().map(|b| format!("{:02x}", b)).collect();
+                return Some(format!("elf:{}", id.concat()));
+            }
+            note = desc.checked_add(align4(descsz))?;
+        }
+    }
+    None
+}
+
+fn align4(n: u64) -> u64 {
+    (n + 3) & !3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::TestMemReader;
+    use super::*;
+
+    fn get_pe_mem_reader() -> TestMemReader {
+        let mut mem = vec![0x0; 0x100];
+        mem[0..2].copy_from_slice(b"MZ");
+        mem[0x3c] = 0x40;
+        mem[0x40..0x44].copy_from_slice(b"PE\0\0");
+        // TimeDateStamp
+        mem[0x48..0x4c].copy_from_slice(&0x5f3a_1b2cu32.to_le_bytes());
+        // SizeOfImage
+        mem[0x90..0x94].copy_from_slice(&0x100u32.to_le_bytes());
+        mem[0xc0..0xc8].copy_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x08, 0x00, 0x00, 0x00]);
+        TestMemReader {
+            mem,
+            start_addr: 0x1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fingerprint_pe() {
+        let mut mem = get_pe_mem_reader();
+        assert_eq!(fingerprint(&mem, 0x1000, 0x1100), "pe:5f3a1b2c:100");
+
+        mem.mem[0x40] = 0x0;
+        assert!(fingerprint(&mem, 0x1000, 0x1100).starts_with("hash:100:"));
+    }
+
+    #[test]
+    fn fingerprint_end_of_memory() {
+        // Headers whose fields would be past the end of the address space.
+        for magic in &[&b"MZ"[..], &b"\x7fELF"[..]] {
+            let start_addr = 0u64.wrapping_sub(magic.len() as u64);
+            let mem = TestMemReader {
+                mem: magic.to_vec(),
+                start_addr,
+                ..Default::default()
+            };
+            assert!(fingerprint(&mem, start_addr, u64::MAX).starts_with("hash:"));
+        }
+    }
+
+    #[test]
+    fn fingerprint_hash() {
+        let mut mem = TestMemReader {
+            mem: vec![0x0; 0x20000],
+            start_addr: 0x10000,
+            ..Default::default()
+        };
+        let before = fingerprint(&mem, 0x10000, 0x30000);
+        assert!(before.starts_with("hash:20000:"));
+
+        // Pages past the first are sampled too.
+        mem.mem[0x1e000] = 0x1;
+        assert_ne!(fingerprint(&mem, 0x10000, 0x30000), before);
+    }
+
+    #[test]
+    fn fingerprint_elf() {
+        let mut mem = vec![0x0; 0x100];
+        mem[0..4].copy_from_slice(b"\x7fELF");
+        mem[4] = 2;
+        // e_phoff, e_phentsize and e_phnum
+        mem[0x20] = 0x40;
+        mem[0x36] = 0x38;
+        mem[0x38] = 2;
+        // A PT_LOAD followed by a PT_NOTE at 0xc0.
+        mem[0x40] = 1;
+        mem[0x78] = 4;
+        mem[0x88] = 0xc0;
+        mem[0x98] = 0x28;
+        // An unrelated note, then the build id.
+        mem[0xc0..0xcc].copy_from_slice(&[3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0]);
+        mem[0xcc..0xd4].copy_from_slice(b"ab\0\0\xff\xff\0\0");
+        mem[0xd4..0xe0].copy_from_slice(&[4, 0, 0, 0, 4, 0, 0, 0, 3, 0, 0, 0]);
+        mem[0xe0..0xe8].copy_from_slice(b"GNU\0\xde\xad\xbe\xef");
+        let mut mem = TestMemReader {
+            mem,
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        assert_eq!(fingerprint(&mem, 0x1000, 0x1100), "elf:deadbeef");
+
+        // Corrupt sizes and offsets fall back to hashing.
+        mem.mem[0xd8..0xdc].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(fingerprint(&mem, 0x1000, 0x1100).starts_with("hash:100:"));
+        mem.mem[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(fingerprint(&mem, 0x1000, 0x1100).starts_with("hash:100:"));
+    }
+
+    #[test]
+    fn cache_reuse() -> Result<(), Error> {
+        let mut mem = get_pe_mem_reader();
+        let sig: Signature = "asm(00112233^^^^^^^^)".parse()?;
+
+        let mut cache = ResolveCache::default();
+        assert_eq!(cache.resolve(&sig, &mem, 0x1000, 0x1100), Ok(0x10d0));
+        assert!(cache.is_dirty());
+        assert_eq!(
+            cache.entries.get("asm(00112233^^^^^^^^)"),
+            Some(&CacheEntry {
+                alternative: 0,
+                offset: 0xd0
+            })
+        );
+
+        let mut buf = Vec::new();
+        cache.write(&mut buf)?;
+        assert!(!cache.is_dirty());
+        let mut cache = ResolveCache::new(&mut buf.as_slice())?;
+
+        // The pattern no longer matches but the module is the same so the
+        // cached offset is used, relative to the new load address.
+        mem.mem[0xc0] = 0xff;
+        mem.start_addr = 0x2000;
+        assert_eq!(cache.resolve(&sig, &mem, 0x2000, 0x2100), Ok(0x20d0));
+        assert!(!cache.is_dirty());
+
+        // A new build invalidates the cache.
+        let mut cache = ResolveCache::new(&mut buf.as_slice())?;
+        mem.mem[0x48] = 0x0;
+        assert!(cache.resolve(&sig, &mem, 0x2000, 0x2100).is_err());
+        assert!(cache.entries.is_empty());
+        assert!(cache.is_dirty());
+        Ok(())
+    }
+
+    #[test]
+    fn cache_prefix() -> Result<(), Error> {
+        let mut mem = get_pe_mem_reader();
+        mem.modules.insert("other.dll".to_string(), 0x8000);
+        mem.mem[0xd0..0xd8].copy_from_slice(&0x1234u64.to_le_bytes());
+        let sig: Signature = "asm(00112233^^^^^^^^); ptr(0)".parse()?;
+        let other: Signature = "module(\"other.dll\")+0x10".parse()?;
+
+        let mut cache = ResolveCache::default();
+        assert_eq!(cache.resolve(&sig, &mem, 0x1000, 0x1100), Ok(0x1234));
+        assert_eq!(cache.resolve(&other, &mem, 0x1000, 0x1100), Ok(0x8010));
+        assert_eq!(
+            cache.entries.get("asm(00112233^^^^^^^^); ptr(0)"),
+            Some(&CacheEntry {
+                alternative: 0,
+                offset: 0xd0
+            })
+        );
+        assert_eq!(cache.entries.len(), 1);
+
+        // The pointer is still followed when the cached offset is used.
+        mem.mem[0xc0] = 0xff;
+        mem.mem[0xd0..0xd8].copy_from_slice(&0x5678u64.to_le_bytes());
+        mem.modules.insert("other.dll".to_string(), 0x9000);
+        assert_eq!(cache.resolve(&sig, &mem, 0x1000, 0x1100), Ok(0x5678));
+        assert_eq!(cache.resolve(&other, &mem, 0x1000, 0x1100), Ok(0x9010));
+        Ok(())
+    }
+}
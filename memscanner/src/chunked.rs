@@ -0,0 +1,148 @@
+use super::{Address, MemReader};
+use failure::Error;
+use std::io::Read;
+
+// Memory is read this many bytes at a time when scanning.
+pub(crate) const CHUNK_SIZE: u64 = 0x10000;
+
+// Unreadable memory is skipped a page at a time.
+pub(crate) const PAGE_SIZE: u64 = 0x1000;
+
+// Reads the values of `size` bytes at every multiple of `stride` in a range
+// of memory, a chunk at a time.  A short read skips ahead to the next page.
+//
+// `size` must be at most `CHUNK_SIZE` and `stride` must not be 0.
+pub(crate) struct ValueReader<'a> {
+    mem: &'a dyn MemReader,
+    end_addr: u64,
+    size: usize,
+    stride: u64,
+    buf: Vec<u8>,
+    // The address of `buf` and how much of it was read.
+    chunk_addr: u64,
+    read: usize,
+    // The offset in `buf` of the next value.
+    offset: usize,
+    // Where the chunk after this one starts, if there is one.
+    next_addr: Option<u64>,
+}
+
+impl<'a> ValueReader<'a> {
+    pub(crate) fn new(
+        mem: &'a dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+        size: usize,
+        stride: u64,
+    ) -> Self {
+        ValueReader {
+            mem,
+            end_addr,
+            size,
+            stride,
+            buf: vec![0x0; end_addr.saturating_sub(start_addr).min(CHUNK_SIZE) as usize],
+            chunk_addr: 0,
+            read: 0,
+            offset: 0,
+            next_addr: align_up(start_addr, stride).filter(|addr| *addr < end_addr),
+        }
+    }
+
+    // Returns the address and bytes of the next readable value.
+    pub(crate) fn next_value(&mut self) -> Option<(u64, &[u8])> {
+        while self.offset + self.size > self.read {
+            let addr = self.next_addr?;
+            self.read_chunk(addr);
+        }
+        let offset = self.offset;
+        self.offset += self.stride as usize;
+        Some((
+            self.chunk_addr + offset as u64,
+            &self.buf[offset..offset + self.size],
+        ))
+    }
+
+    fn read_chunk(&mut self, addr: u64) {
+        let want = (self.end_addr - addr).min(CHUNK_SIZE) as usize;
+        self.read = self.mem.read(&mut self.buf[..want], addr, want);
+        self.chunk_addr = addr;
+        self.offset = 0;
+
+        let tested = match self.read >= self.size {
+            true => (self.read - self.size) as u64 / self.stride + 1,
+            false => 0,
+        };
+        let next = if self.read < want {
+            // Skip past the page that couldn't be read.
+            ((addr + self.read as u64) / PAGE_SIZE)
+                .checked_add(1)
+                .and_then(|page| page.checked_mul(PAGE_SIZE))
+        } else if want as u64 == self.end_addr - addr {
+            None
+        } else {
+            // Values overlapping the end of the chunk are read with the next.
+            Some(addr + tested * self.stride)
+        };
+        self.next_addr = next
+            .and_then(|next| align_up(next, self.stride))
+            .filter(|next| *next < self.end_addr);
+    }
+}
+
+fn align_up(addr: u64, align: u64) -> Option<u64> {
+    Address(addr)
+        .advance((align - addr % align) % align)
+        .ok()
+        .map(|a| a.0)
+}
+
+// Read a little endian u64 from a snapshot or candidate file.
+pub(crate) fn read_u64(reader: &mut impl Read) -> Result<u64, Error> {
+    let mut buf = [0x0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::TestMemReader;
+    use super::*;
+
+    // Collect the addresses of the first byte of every value read.
+    fn read_all(mem: &TestMemReader, start_addr: u64, end_addr: u64, stride: u64) -> Vec<u64> {
+        let mut reader = ValueReader::new(mem, start_addr, end_addr, 4, stride);
+        let mut addrs = vec![];
+        while let Some((addr, bytes)) = reader.next_value() {
+            assert_eq!(bytes[0], (addr - mem.start_addr) as u8);
+            addrs.push(addr);
+        }
+        addrs
+    }
+
+    #[test]
+    fn chunks_and_pages() {
+        // Two chunks followed by a partial page.
+        let len = 2 * CHUNK_SIZE + 0x10;
+        let mem = TestMemReader {
+            mem: (0..len).map(|i| i as u8).collect(),
+            start_addr: 0x10000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + len;
+
+        // Values overlapping the end of a chunk are still read.
+        let addrs = read_all(&mem, 0x10000, 0x20006, 1);
+        assert_eq!(addrs, (0x10000..=0x20002).collect::<Vec<u64>>());
+
+        // The rest of the range is past the end of `mem`.
+        let addrs = read_all(&mem, end_addr - 0x8, end_addr + 2 * PAGE_SIZE, 4);
+        assert_eq!(addrs, vec![end_addr - 0x8, end_addr - 0x4]);
+
+        // Unaligned starts are rounded up.
+        assert_eq!(read_all(&mem, 0x10001, 0x10010, 8), vec![0x10008]);
+        assert!(read_all(&mem, 0x10001, 0x10008, 8).is_empty());
+
+        let count = read_all(&mem, mem.start_addr, end_addr, 4).len() as u64;
+        assert_eq!(count, len / 4);
+    }
+}
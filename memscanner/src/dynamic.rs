@@ -0,0 +1,433 @@
+use super::macro_helpers::{field_addr, field_value_addr, get_array_base_addr};
+use super::{
+    ArrayConfig, ArrayResolver, ArrayScanner, FieldConfig, FieldType, MemReader, Resolver,
+    Scannable, Scanner, TypeConfig,
+};
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// A value read without a Rust type describing it.
+///
+/// The field types come from the `TypeConfig` instead, so configs can be
+/// scanned by tools that don't know about the game's types.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum DynValue {
+    /// A null entry in a pointer table.
+    #[default]
+    Null,
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<DynValue>),
+    Object(BTreeMap<String, DynValue>),
+}
+
+impl DynValue {
+    /// Returns the field called `name` if this is an object.
+    pub fn get(&self, name: &str) -> Option<&DynValue> {
+        match self {
+            DynValue::Object(fields) => fields.get(name),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            DynValue::Int(v) => Some(v),
+            DynValue::UInt(v) if v <= i64::MAX as u64 => Some(v as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            DynValue::UInt(v) => Some(v),
+            DynValue::Int(v) if v >= 0 => Some(v as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            DynValue::Float(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            DynValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[DynValue]> {
+        match self {
+            DynValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+// A `DynValue` is scanned into an object with a member for each of the
+// config's fields.
+impl Scannable for DynValue {
+    fn type_name() -> &'static str {
+        "DynValue"
+    }
+
+    fn get_resolver(config: TypeConfig) -> Result<Box<Resolver<Self>>, Error> {
+        check_fields(&config.fields, "")?;
+
+        let resolver = move |mem: &dyn MemReader,
+                             start_addr: u64,
+                             end_addr: u64|
+              -> Result<Box<Scanner<Self>>, Error> {
+            let base_addr = config
+                .resolve(mem, start_addr, end_addr)
+                .map_err(|e| format_err!("Can't resolve base address: {}", e))?;
+            let fields = config.fields.clone();
+
+            let scanner = move |obj: &mut DynValue, mem: &dyn MemReader| -> Result<(), Error> {
+                *obj = read_object(&fields, mem, base_addr)?;
+                Ok(())
+            };
+            Ok(Box::new(scanner))
+        };
+        Ok(Box::new(resolver))
+    }
+
+    fn get_array_resolver(config: TypeConfig) -> Result<Box<ArrayResolver<Self>>, Error> {
+        let array_config = config.array.clone().ok_or(format_err!(
+            "Can't create resolver for Vec<DynValue>: no array config."
+        ))?;
+        check_fields(&config.fields, "")?;
+
+        let resolver = move |mem: &dyn MemReader,
+                             start_addr: u64,
+                             end_addr: u64|
+              -> Result<Box<ArrayScanner<Self>>, Error> {
+            let base_addr = config
+                .resolve(mem, start_addr, end_addr)
+                .map_err(|e| format_err!("Can't resolve base address: {}", e))?;
+            let fields = config.fields.clone();
+            let array_config = array_config.clone();
+
+            let scanner =
+                move |vec: &mut Vec<DynValue>, mem: &dyn MemReader| -> Result<(), Error> {
+                    *vec = read_array(&array_config, mem, base_addr, |addr| {
+                        read_object(&fields, mem, addr)
+                    })?;
+                    Ok(())
+                };
+            Ok(Box::new(scanner))
+        };
+        Ok(Box::new(resolver))
+    }
+}
+
+// Check that every field says what to read.  `prefix` is the path to
+// `fields` for errors.
+fn check_fields(fields: &HashMap<String, FieldConfig>, prefix: &str) -> Result<(), Error> {
+    for (name, field) in fields {
+        let name = format!("{}{}", prefix, name);
+        match (field.field_type, field.fields.is_empty()) {
+            (None, true) => return Err(format_err!("field {} has no type", name)),
+            (Some(_), false) => {
+                return Err(format_err!("field {} has both a type and fields", name))
+            }
+            (None, false) => check_fields(&field.fields, &format!("{}.", name))?,
+            (Some(_), true) => (),
+        }
+        let type_size = field.field_type.and_then(FieldType::size);
+        if let (Some(size), Some(type_size)) = (field.size, type_size) {
+            // Values in an inline array can be padded.
+            if size < type_size || (size != type_size && field.count.is_none()) {
+                return Err(format_err!(
+                    "field {} is declared as {} bytes but is {}",
+                    name,
+                    size,
+                    type_size
+                ));
+            }
+        }
+        if field.count.is_some() && field.array.is_some() {
+            return Err(format_err!("field {} has both a count and an array", name));
+        }
+        if field.count.is_some() && field.stride().is_none() {
+            return Err(format_err!("field {} has a count but no size", name));
+        }
+    }
+    Ok(())
+}
+
+fn read_object(
+    fields: &HashMap<String, FieldConfig>,
+    mem: &dyn MemReader,
+    base_addr: u64,
+) -> Result<DynValue, Error> {
+    let mut obj = BTreeMap::new();
+    for (name, field) in fields {
+        let value = read_field(field, mem, base_addr)
+            .map_err(|e| format_err!("can't read {}: {}", name, e))?;
+        obj.insert(name.clone(), value);
+    }
+    Ok(DynValue::Object(obj))
+}
+
+fn read_field(field: &FieldConfig, mem: &dyn MemReader, base_addr: u64) -> Result<DynValue, Error> {
+    let addr = match field_value_addr(mem, base_addr, field)? {
+        Some(addr) => addr,
+        None => return Ok(DynValue::Null),
+    };
+    let read = |addr| match field.field_type {
+        Some(field_type) => read_value(field_type, field.size, mem, addr),
+        None => read_object(&field.fields, mem, addr),
+    };
+    match (&field.array, field.count, field.stride()) {
+        (Some(array), _, _) => read_array(array, mem, addr, read).map(DynValue::Array),
+        (None, Some(count), Some(stride)) => (0..count)
+            .map(|i| {
+                let offset = i
+                    .checked_mul(stride)
+                    .ok_or(format_err!("index {} is out of range", i))?;
+                read(field_addr(addr, offset)?).map_err(|e| format_err!("{}: {}", i, e))
+            })
+            .collect::<Result<_, Error>>()
+            .map(DynValue::Array),
+        _ => read(addr),
+    }
+}
+
+fn read_array(
+    config: &ArrayConfig,
+    mem: &dyn MemReader,
+    base_addr: u64,
+    read: impl Fn(u64) -> Result<DynValue, Error>,
+) -> Result<Vec<DynValue>, Error> {
+    (0..config.element_count as usize)
+        .map(|i| {
+            let addr = get_array_base_addr(config, base_addr, i, mem)?;
+            // Pointer tables can have null entries.
+            if addr == 0x0 {
+                return Ok(DynValue::Null);
+            }
+            read(addr).map_err(|e| format_err!("{}: {}", i, e))
+        })
+        .collect()
+}
+
+// Read a value of `field_type`.  Strings are read up to `size` bytes.
+fn read_value(
+    field_type: FieldType,
+    size: Option<u64>,
+    mem: &dyn MemReader,
+    addr: u64,
+) -> Result<DynValue, Error> {
+    let value = match field_type {
+        FieldType::U8 => mem.read_u8(addr).map(|v| DynValue::UInt(v.into())),
+        FieldType::I8 => mem.read_u8(addr).map(|v| DynValue::Int((v as i8).into())),
+        FieldType::U16 => mem.read_u16(addr).map(|v| DynValue::UInt(v.into())),
+        FieldType::I16 => mem.read_i16(addr).map(|v| DynValue::Int(v.into())),
+        FieldType::U32 => mem.read_u32(addr).map(|v| DynValue::UInt(v.into())),
+        FieldType::I32 => mem.read_i32(addr).map(|v| DynValue::Int(v.into())),
+        FieldType::U64 => mem.read_u64(addr).map(DynValue::UInt),
+        FieldType::I64 => mem.read_i64(addr).map(DynValue::Int),
+        FieldType::F32 => mem.read_f32(addr).map(|v| DynValue::Float(v.into())),
+        FieldType::F64 => mem.read_f64(addr).map(DynValue::Float),
+        FieldType::String => match size {
+            Some(size) => mem.read_string_max(addr, size),
+            None => mem.read_string(addr),
+        }
+        .map(DynValue::String),
+    };
+    value.ok_or(format_err!("can't read value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::TestMemReader;
+    use super::*;
+
+    fn get_mem_reader() -> TestMemReader {
+        #[rustfmt::skip]
+        let r = TestMemReader {
+            mem: vec![
+                0x00, 0x11, 0x22, 0x33, 0x00, 0x00, 0x00, 0x00, // 0x1000
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x3f, // 0x1008
+                0x30, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0x1010
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0x1018
+                0x38, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0x1020
+                0x4d, 0x65, 0x6d, 0x00, 0xfe, 0xff, 0x00, 0x00, // 0x1028
+                0x07, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, // 0x1030
+                0x09, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, // 0x1038
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        r
+    }
+
+    fn config(text: &str) -> TypeConfig {
+        TypeConfig::new(&mut text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn scan_object() -> Result<(), Error> {
+        let config = config(
+            "{
+                signature: \"base\",
+                fields: {
+                    magic: { offset: 0x0, type: \"u32\" },
+                    scale: { offset: 0xc, type: \"f32\" },
+                    name: {
+                        offset: 0x28,
+                        fields: {
+                            text: { offset: 0x0, type: \"string\" },
+                            delta: { offset: 0x4, type: \"i16\" },
+                        },
+                    },
+                    items: {
+                        offset: 0x10,
+                        type: \"u32\",
+                        array: { element_size: 8, element_count: 3, uses_pointer_table: true },
+                    },
+                },
+            }",
+        );
+        let mut buf = Vec::new();
+        config.write(&mut buf)?;
+        assert_eq!(TypeConfig::new(&mut buf.as_slice())?.fields, config.fields);
+
+        let mem = get_mem_reader();
+        let resolver = DynValue::get_resolver(config)?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+
+        let mut obj = DynValue::default();
+        scanner(&mut obj, &mem)?;
+        assert_eq!(
+            obj.get("magic").and_then(DynValue::as_u64),
+            Some(0x33221100)
+        );
+        assert_eq!(obj.get("scale").and_then(DynValue::as_f64), Some(1.5));
+        let name = obj.get("name").unwrap();
+        assert_eq!(name.get("text").and_then(DynValue::as_str), Some("Mem"));
+        assert_eq!(name.get("delta").and_then(DynValue::as_i64), Some(-2));
+        assert_eq!(
+            obj.get("items").and_then(DynValue::as_array),
+            Some(&[DynValue::UInt(7), DynValue::Null, DynValue::UInt(9)][..])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn scan_array() -> Result<(), Error> {
+        let config = config(
+            "{
+                signature: \"base+0x30\",
+                array: { element_size: 4, element_count: 4 },
+                fields: { value: { offset: 0x0, type: \"u8\" } },
+            }",
+        );
+        let mem = get_mem_reader();
+        let resolver = DynValue::get_array_resolver(config)?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+
+        let mut values = vec![];
+        scanner(&mut values, &mem)?;
+        let values: Vec<Option<u64>> = values
+            .iter()
+            .map(|v| v.get("value").and_then(DynValue::as_u64))
+            .collect();
+        assert_eq!(values, vec![Some(7), Some(8), Some(9), Some(10)]);
+        Ok(())
+    }
+
+    #[test]
+    fn typed_fields() -> Result<(), Error> {
+        let config = config(
+            "{
+                signature: \"base\",
+                fields: {
+                    first: { offset: 0x20, type: \"u32\", ptr: true },
+                    missing: { offset: 0x18, type: \"u32\", ptr: true },
+                    short: { offset: 0x28, type: \"string\", size: 2 },
+                    counts: { offset: 0x30, type: \"u16\", size: 4, count: 3 },
+                    pairs: {
+                        offset: 0x30,
+                        size: 8,
+                        count: 2,
+                        fields: { a: { offset: 0x0, type: \"u8\" } },
+                    },
+                },
+            }",
+        );
+        let mem = get_mem_reader();
+        let resolver = DynValue::get_resolver(config)?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+
+        let mut obj = DynValue::default();
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.get("first"), Some(&DynValue::UInt(9)));
+        assert_eq!(obj.get("missing"), Some(&DynValue::Null));
+        assert_eq!(obj.get("short").and_then(DynValue::as_str), Some("Me"));
+        assert_eq!(
+            obj.get("counts").and_then(DynValue::as_array),
+            Some(&[DynValue::UInt(7), DynValue::UInt(8), DynValue::UInt(9)][..])
+        );
+        let pairs: Vec<Option<u64>> = obj
+            .get("pairs")
+            .and_then(DynValue::as_array)
+            .unwrap()
+            .iter()
+            .map(|v| v.get("a").and_then(DynValue::as_u64))
+            .collect();
+        assert_eq!(pairs, vec![Some(7), Some(9)]);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_fields() {
+        let untyped = config("{ signature: [\"asm(00112233)\"], fields: { a: 0x0 } }");
+        assert_eq!(
+            DynValue::get_resolver(untyped).err().map(|e| e.to_string()),
+            Some("field a has no type".to_string())
+        );
+
+        let nested = config(
+            "{
+                signature: [\"asm(00112233)\"],
+                fields: { a: { offset: 0x0, fields: { b: 0x4 } } },
+            }",
+        );
+        assert_eq!(
+            DynValue::get_resolver(nested).err().map(|e| e.to_string()),
+            Some("field a.b has no type".to_string())
+        );
+
+        let sized = config(
+            "{
+                signature: [\"asm(00112233)\"],
+                fields: { a: { offset: 0x0, type: \"u16\", size: 4 } },
+            }",
+        );
+        assert_eq!(
+            DynValue::get_resolver(sized).err().map(|e| e.to_string()),
+            Some("field a is declared as 4 bytes but is 2".to_string())
+        );
+
+        let counted = config(
+            "{
+                signature: [\"asm(00112233)\"],
+                fields: { a: { offset: 0x0, count: 2, fields: { b: { offset: 0x0, type: \"u8\" } } } },
+            }",
+        );
+        assert_eq!(
+            DynValue::get_resolver(counted).err().map(|e| e.to_string()),
+            Some("field a has a count but no size".to_string())
+        );
+    }
+}
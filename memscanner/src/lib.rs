@@ -1,21 +1,36 @@
+pub mod address;
+pub mod cache;
+mod chunked;
+pub mod dynamic;
 pub mod macro_helpers;
+pub mod pointer_scan;
 pub mod process;
+pub mod registry;
 pub mod signature;
+pub mod snapshot;
 pub mod test;
+pub mod validate;
+pub mod value_scan;
 
 use failure::Error;
 use json5;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::io::Read;
+use std::fmt;
+use std::io::{Read, Write};
 
+pub use address::{Address, AddressError};
+pub use cache::{ResolveCache, SharedResolveCache};
+pub use dynamic::DynValue;
 pub use memscanner_derive::{Scannable, ScannableEnum};
-pub use signature::Signature;
+pub use registry::{ConfigRegistry, ConfigVariant};
+pub use signature::{ResolveError, Signature};
+pub use snapshot::Snapshot;
 
 macro_rules! read_type_impl {
     ($type: ty, $func_name: tt) => {
-        fn $func_name (&self, addr: u64) -> Option<$type> {
+        fn $func_name(&self, addr: u64) -> Option<$type> {
             let len = std::mem::size_of::<$type>();
             let mut buf: Vec<u8> = vec![0; len];
             let read_bytes = self.read(&mut buf, addr, len);
@@ -24,31 +39,52 @@ macro_rules! read_type_impl {
             }
             Some(<$type>::from_ne_bytes((&buf as &[u8]).try_into().ok()?))
         }
-    }
+    };
 }
 
 macro_rules! read_float_impl {
     ($type: ty, $int_type: ty, $func_name: tt) => {
-        fn $func_name (&self, addr: u64) -> Option<$type> {
+        fn $func_name(&self, addr: u64) -> Option<$type> {
             let len = std::mem::size_of::<$type>();
             let mut buf: Vec<u8> = vec![0; len];
             let read_bytes = self.read(&mut buf, addr, len);
             if read_bytes != len {
                 return None;
             }
-            Some(<$type>::from_bits(<$int_type>::from_ne_bytes((&buf as &[u8]).try_into().ok()?)))
+            Some(<$type>::from_bits(<$int_type>::from_ne_bytes(
+                (&buf as &[u8]).try_into().ok()?,
+            )))
         }
-    }
+    };
 }
 
 /// The `MemReader` trait allows for reading bytes form a memory source.
+///
+/// Addresses are plain `u64`s for convenience.  Arithmetic on them should go
+/// through `Address` so it can't wrap.
 pub trait MemReader {
     /// Read bytes `len` bytes at `addr` from the `MemReader` and write them
     /// to `buf`.  
     ///
+    /// Implementations must not read more than `buf.len()` bytes and must
+    /// not panic on any `addr`.
+    ///
     /// Returns: number of bytes actually read.
     fn read(&self, buf: &mut [u8], addr: u64, len: usize) -> usize;
 
+    /// Returns the base address of the module called `name`.
+    ///
+    /// Returns `None` if the module is not loaded or the `MemReader` has no
+    /// notion of modules.
+    fn module_base(&self, _name: &str) -> Option<u64> {
+        None
+    }
+
+    /// Returns true if the byte at `addr` can be read.
+    fn is_mapped(&self, addr: u64) -> bool {
+        self.read_u8(addr).is_some()
+    }
+
     fn read_u8(&self, addr: u64) -> Option<u8> {
         let mut val: Vec<u8> = vec![0; 1];
         let read_bytes = self.read(&mut val, addr, 1);
@@ -59,11 +95,15 @@ pub trait MemReader {
     }
 
     fn read_string(&self, addr: u64) -> Option<String> {
-        let string_limit = 32;
+        self.read_string_max(addr, 32)
+    }
+
+    /// Read a nul terminated string of at most `max_len` bytes.
+    fn read_string_max(&self, addr: u64, max_len: u64) -> Option<String> {
         let mut bytes: Vec<u8> = Vec::new();
 
-        for i in 0..string_limit {
-            let b = self.read_u8(addr + i)?;
+        for i in 0..max_len {
+            let b = self.read_u8(Address(addr).advance(i).ok()?.0)?;
             if b == 0x0 {
                 break;
             }
@@ -84,29 +124,181 @@ pub trait MemReader {
     read_float_impl!(f64, u64, read_f64);
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ArrayConfig {
     pub element_size: u64,
     pub element_count: u64,
     pub uses_pointer_table: Option<bool>,
 }
 
-#[derive(Debug, Deserialize)]
-struct TypeConfigIntermediate {
-    signature: Vec<String>,
-    array: Option<ArrayConfig>,
-    fields: HashMap<String, u64>,
+/// The type a field holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    String,
+}
+
+impl FieldType {
+    /// The size of the type in bytes, or `None` for strings.
+    pub fn size(self) -> Option<u64> {
+        match self {
+            FieldType::U8 | FieldType::I8 => Some(1),
+            FieldType::U16 | FieldType::I16 => Some(2),
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => Some(4),
+            FieldType::U64 | FieldType::I64 | FieldType::F64 => Some(8),
+            FieldType::String => None,
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FieldType::U8 => "u8",
+            FieldType::I8 => "i8",
+            FieldType::U16 => "u16",
+            FieldType::I16 => "i16",
+            FieldType::U32 => "u32",
+            FieldType::I32 => "i32",
+            FieldType::U64 => "u64",
+            FieldType::I64 => "i64",
+            FieldType::F32 => "f32",
+            FieldType::F64 => "f64",
+            FieldType::String => "string",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where a field lives in its struct and, optionally, what it holds.
+///
+/// In configs a field is either a bare offset or an object like
+/// `{ offset: 0x10, type: "u32" }`.  A field with `fields` is a nested
+/// struct whose offsets are relative to the field, and a field with `array`
+/// is an array of its type.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "FieldConfigRepr", into = "FieldConfigRepr")]
+pub struct FieldConfig {
+    pub offset: u64,
+    pub field_type: Option<FieldType>,
+    /// The size of the field in bytes, or of each value if there is a
+    /// `count`.  Strings are read up to this many bytes.
+    pub size: Option<u64>,
+    /// The field is an inline array of `count` values, `size` bytes apart.
+    pub count: Option<u64>,
+    /// The field holds a pointer to its value rather than the value.  A
+    /// null pointer leaves a struct member unchanged.
+    pub ptr: bool,
+    pub fields: HashMap<String, FieldConfig>,
+    pub array: Option<ArrayConfig>,
+}
+
+impl FieldConfig {
+    /// The distance between values of an inline array.
+    pub fn stride(&self) -> Option<u64> {
+        self.size.or_else(|| self.field_type?.size())
+    }
+
+    // True if the field is just an offset.
+    fn is_offset(&self) -> bool {
+        *self == FieldConfig::from(self.offset)
+    }
+}
+
+impl From<u64> for FieldConfig {
+    fn from(offset: u64) -> Self {
+        FieldConfig {
+            offset,
+            ..Default::default()
+        }
+    }
+}
+
+// The forms a `FieldConfig` can take in a config.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum FieldConfigRepr {
+    Offset(u64),
+    Field {
+        offset: u64,
+        #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+        field_type: Option<FieldType>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u64>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        ptr: bool,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        fields: HashMap<String, FieldConfig>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        array: Option<ArrayConfig>,
+    },
+}
+
+impl From<FieldConfigRepr> for FieldConfig {
+    fn from(repr: FieldConfigRepr) -> Self {
+        match repr {
+            FieldConfigRepr::Offset(offset) => offset.into(),
+            FieldConfigRepr::Field {
+                offset,
+                field_type,
+                size,
+                count,
+                ptr,
+                fields,
+                array,
+            } => FieldConfig {
+                offset,
+                field_type,
+                size,
+                count,
+                ptr,
+                fields,
+                array,
+            },
+        }
+    }
+}
+
+impl From<FieldConfig> for FieldConfigRepr {
+    fn from(field: FieldConfig) -> Self {
+        if field.is_offset() {
+            return FieldConfigRepr::Offset(field.offset);
+        }
+        FieldConfigRepr::Field {
+            offset: field.offset,
+            field_type: field.field_type,
+            size: field.size,
+            count: field.count,
+            ptr: field.ptr,
+            fields: field.fields,
+            array: field.array,
+        }
+    }
 }
 
 /// A configuration describing how to find a piece of memory and map it to
 /// a struct.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TypeConfig {
-    // TODO: Implement a custom deserializer for that type which parses the strings.
-    // this will avoid the need for the intermediate type above.
     pub signature: signature::Signature,
     pub array: Option<ArrayConfig>,
-    pub fields: HashMap<String, u64>,
+    pub fields: HashMap<String, FieldConfig>,
+
+    /// Resolutions are looked up in and added to this cache when set.
+    #[serde(skip)]
+    pub cache: Option<SharedResolveCache>,
 }
 
 impl TypeConfig {
@@ -115,14 +307,38 @@ impl TypeConfig {
         let mut buffer = String::new();
         reader.read_to_string(&mut buffer)?;
 
-        let inter: TypeConfigIntermediate = json5::from_str(&buffer)?;
-        let sig = signature::Signature::new(&inter.signature)?;
+        Ok(json5::from_str(&buffer)?)
+    }
+
+    /// Write the config as json5.  The output can be read back with `new`.
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.write_all(json5::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
 
-        Ok(TypeConfig {
-            signature: sig,
-            array: inter.array,
-            fields: inter.fields,
-        })
+    /// Use `cache` when resolving the signature.
+    pub fn with_cache(mut self, cache: SharedResolveCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Resolve the config's signature, going through the cache if there is
+    /// one.
+    pub fn resolve(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+    ) -> Result<u64, ResolveError> {
+        match &self.cache {
+            Some(cache) => {
+                // A panic while holding the lock can't leave the cache in an
+                // inconsistent state.
+                let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+                cache.resolve(&self.signature, mem, start_addr, end_addr)
+            }
+            None => self.signature.resolve(mem, start_addr, end_addr),
+        }
     }
 }
 
@@ -152,6 +368,9 @@ pub trait Scannable
 where
     Self: std::marker::Sized,
 {
+    /// The name the type's config has in a `ConfigRegistry`.
+    fn type_name() -> &'static str;
+
     /// Returns a `Resolver` capable of finding the `Scannable` described by
     /// the `config`.
     fn get_resolver(config: TypeConfig) -> Result<Box<Resolver<Self>>, Error>;
@@ -164,12 +383,31 @@ where
 pub trait ScannableValue<T> {
     /// Scans the value at `addr` using `mem` to read its value.
     fn scan_val(&mut self, mem: &dyn MemReader, addr: u64) -> Result<(), Error>;
+
+    /// Scans the value of `field` at `addr`.  Types that can be read more
+    /// than one way, like strings, use the field's config to choose.
+    fn scan_field(
+        &mut self,
+        mem: &dyn MemReader,
+        addr: u64,
+        _field: &FieldConfig,
+    ) -> Result<(), Error> {
+        self.scan_val(mem, addr)
+    }
+
+    /// The `FieldType` a config may declare for the value, if there is one.
+    fn field_type() -> Option<FieldType>
+    where
+        Self: Sized,
+    {
+        None
+    }
 }
 
 // A macro to generate implementations of ScannableValue for types that have
 // direct MemReader readers.
 macro_rules! scannable_value_impl {
-    ($type: ty, $func_name: tt) => {
+    ($type: ty, $func_name: tt, $field_type: expr) => {
         impl ScannableValue<$type> for $type {
             fn scan_val(&mut self, mem: &dyn MemReader, addr: u64) -> Result<(), Error> {
                 use failure::format_err;
@@ -178,19 +416,49 @@ macro_rules! scannable_value_impl {
                     .ok_or(format_err!("can't read value"))?;
                 Ok(())
             }
+
+            fn field_type() -> Option<FieldType> {
+                Some($field_type)
+            }
         }
     };
 }
 
-scannable_value_impl!(String, read_string);
+impl ScannableValue<String> for String {
+    fn scan_val(&mut self, mem: &dyn MemReader, addr: u64) -> Result<(), Error> {
+        *self = mem
+            .read_string(addr)
+            .ok_or(failure::format_err!("can't read value"))?;
+        Ok(())
+    }
+
+    // Strings are read up to the field's size, if it has one.
+    fn scan_field(
+        &mut self,
+        mem: &dyn MemReader,
+        addr: u64,
+        field: &FieldConfig,
+    ) -> Result<(), Error> {
+        *self = match field.size {
+            Some(size) => mem.read_string_max(addr, size),
+            None => mem.read_string(addr),
+        }
+        .ok_or(failure::format_err!("can't read value"))?;
+        Ok(())
+    }
+
+    fn field_type() -> Option<FieldType> {
+        Some(FieldType::String)
+    }
+}
 
-scannable_value_impl!(u8, read_u8);
-scannable_value_impl!(u16, read_u16);
-scannable_value_impl!(i16, read_i16);
-scannable_value_impl!(u32, read_u32);
-scannable_value_impl!(i32, read_i32);
-scannable_value_impl!(u64, read_u64);
-scannable_value_impl!(i64, read_i64);
+scannable_value_impl!(u8, read_u8, FieldType::U8);
+scannable_value_impl!(u16, read_u16, FieldType::U16);
+scannable_value_impl!(i16, read_i16, FieldType::I16);
+scannable_value_impl!(u32, read_u32, FieldType::U32);
+scannable_value_impl!(i32, read_i32, FieldType::I32);
+scannable_value_impl!(u64, read_u64, FieldType::U64);
+scannable_value_impl!(i64, read_i64, FieldType::I64);
 
-scannable_value_impl!(f32, read_f32);
-scannable_value_impl!(f64, read_f64);
+scannable_value_impl!(f32, read_f32, FieldType::F32);
+scannable_value_impl!(f64, read_f64, FieldType::F64);
@@ -1,6 +1,6 @@
 use super::test::TestMemReader;
-use super::ArrayConfig;
-use super::MemReader;
+use super::{Address, MemReader};
+use super::{ArrayConfig, FieldConfig, FieldType};
 use failure::{format_err, Error};
 use num_traits::FromPrimitive;
 
@@ -12,6 +12,7 @@ pub fn new_mem_cache(config: &ArrayConfig) -> TestMemReader {
     TestMemReader {
         mem: data,
         start_addr: 0x0,
+        ..Default::default()
     }
 }
 
@@ -29,17 +30,90 @@ pub fn update_mem_cache(
     Ok(())
 }
 
+/// The address of the field `offset` bytes into the struct at `base_addr`.
+pub fn field_addr(base_addr: u64, offset: u64) -> Result<u64, Error> {
+    Ok(Address(base_addr).advance(offset)?.0)
+}
+
+/// The address of `field`'s value in the struct at `base_addr`, following
+/// the field's pointer if it has one.  Returns `None` for null pointers.
+pub fn field_value_addr(
+    mem: &dyn MemReader,
+    base_addr: u64,
+    field: &FieldConfig,
+) -> Result<Option<u64>, Error> {
+    let addr = field_addr(base_addr, field.offset)?;
+    if !field.ptr {
+        return Ok(Some(addr));
+    }
+    match mem.read_u64(addr) {
+        Some(0x0) => Ok(None),
+        Some(ptr) => Ok(Some(Address(ptr).canonical()?.0)),
+        None => Err(format_err!("can't read pointer at 0x{:x}", addr)),
+    }
+}
+
+/// Check that `field` can be read into a struct member of `field_type`.
+pub fn check_field(
+    name: &str,
+    field: &FieldConfig,
+    field_type: Option<FieldType>,
+) -> Result<(), Error> {
+    if let (Some(declared), Some(field_type)) = (field.field_type, field_type) {
+        if declared != field_type {
+            return Err(format_err!(
+                "{} is declared as {} but is {}",
+                name,
+                declared,
+                field_type
+            ));
+        }
+    }
+    // Strings are read up to `size` bytes and the size of types without a
+    // `FieldType` is unknown, so only fixed size types are checked.
+    if let (Some(size), Some(type_size)) = (field.size, field_type.and_then(FieldType::size)) {
+        if size != type_size {
+            return Err(format_err!(
+                "{} is declared as {} bytes but is {}",
+                name,
+                size,
+                type_size
+            ));
+        }
+    }
+    if field.count.is_some() || field.array.is_some() || !field.fields.is_empty() {
+        return Err(format_err!(
+            "{} is an array or struct, which can only be read as a DynValue",
+            name
+        ));
+    }
+    Ok(())
+}
+
 pub fn get_array_base_addr(
     config: &ArrayConfig,
     base_addr: u64,
     index: usize,
     mem: &dyn MemReader,
 ) -> Result<u64, Error> {
-    Ok(match config.uses_pointer_table.unwrap_or(false) {
-        false => base_addr + index as u64 * config.element_size,
-        true => mem
-            .read_u64(base_addr + index as u64 * 8)
-            .ok_or(format_err! {"Can't load pointer table index {}", index})?,
+    let uses_pointer_table = config.uses_pointer_table.unwrap_or(false);
+    let stride = if uses_pointer_table {
+        8
+    } else {
+        config.element_size
+    };
+    let offset = (index as u64)
+        .checked_mul(stride)
+        .ok_or(format_err!("array index {} is out of range", index))?;
+    let addr = Address(base_addr).advance(offset)?.0;
+    Ok(match uses_pointer_table {
+        false => addr,
+        true => {
+            let ptr = mem
+                .read_u64(addr)
+                .ok_or(format_err! {"Can't load pointer table index {}", index})?;
+            Address(ptr).canonical()?.0
+        }
     })
 }
 
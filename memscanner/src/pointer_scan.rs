@@ -0,0 +1,339 @@
+use super::chunked::ValueReader;
+use super::signature::Signature;
+use super::MemReader;
+use failure::Error;
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::fmt;
+
+/// A module whose static data can anchor a pointer path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaticRegion {
+    pub module: String,
+    /// The module's base address.
+    pub start_addr: u64,
+    pub end_addr: u64,
+}
+
+/// Limits on how far `pointer_scan` searches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PointerScanOptions {
+    /// The most pointers a path may dereference.
+    pub max_depth: usize,
+    /// The largest offset added to a pointer to reach the next one.
+    pub max_offset: u32,
+    /// Pointers are only looked for at multiples of `align`.
+    pub align: u64,
+    /// The search stops after finding this many paths.
+    pub max_results: usize,
+    /// The search stops after following this many pointers, found or not.
+    /// Dense pointer graphs have far more paths than are worth walking.
+    pub max_visits: usize,
+}
+
+impl Default for PointerScanOptions {
+    fn default() -> Self {
+        PointerScanOptions {
+            max_depth: 4,
+            max_offset: 0x800,
+            align: 8,
+            max_results: 10000,
+            max_visits: 1_000_000,
+        }
+    }
+}
+
+/// A chain of pointers from a module's static data to an address.
+///
+/// The pointer at `module + module_offset` is read, then each offset but
+/// the last is added to the current pointer and the pointer there is read.
+/// The last offset is added to the final pointer.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PointerPath {
+    pub module: String,
+    pub module_offset: u32,
+    /// Offsets from the outermost pointer to the innermost.
+    pub offsets: Vec<u32>,
+}
+
+impl PointerPath {
+    /// The path as `Signature` ops.
+    pub fn to_ops(&self) -> Vec<String> {
+        let mut ops = vec![
+            format!("module(\"{}\")+0x{:x}", self.module, self.module_offset),
+            "ptr(0)".to_string(),
+        ];
+        if let Some((last, rest)) = self.offsets.split_last() {
+            ops.extend(rest.iter().map(|o| format!("ptr({})", o)));
+            if *last != 0 {
+                ops.push(format!("add({})", last));
+            }
+        }
+        ops
+    }
+
+    pub fn to_signature(&self) -> Result<Signature, Error> {
+        Signature::new(&self.to_ops())
+    }
+
+    /// Follow the path in `mem`.  Returns `None` if a pointer can't be
+    /// read or the module isn't loaded.
+    pub fn resolve(&self, mem: &dyn MemReader) -> Option<u64> {
+        self.to_signature().ok()?.resolve(mem, 0, 0).ok()
+    }
+}
+
+impl fmt::Display for PointerPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_ops().join("; "))
+    }
+}
+
+/// Find pointer paths from the static data of `statics` to `target`.
+///
+/// Pointers are looked for in `ranges` and `statics`, and only values
+/// pointing into one of them are considered pointers.  Paths are returned
+/// shortest first.
+pub fn pointer_scan(
+    mem: &dyn MemReader,
+    target: u64,
+    statics: &[StaticRegion],
+    ranges: &[(u64, u64)],
+    options: &PointerScanOptions,
+) -> Vec<PointerPath> {
+    let mut regions: Vec<(u64, u64)> = ranges.to_vec();
+    regions.extend(statics.iter().map(|s| (s.start_addr, s.end_addr)));
+    let scanner = PointerScanner {
+        map: pointer_map(mem, &regions, options.align.max(1)),
+        statics,
+        options,
+        visits: Cell::new(0),
+    };
+
+    let mut paths = vec![];
+    scanner.search(target, &mut vec![], &mut vec![target], &mut paths);
+    paths.sort_by(|a, b| a.offsets.len().cmp(&b.offsets.len()).then(a.cmp(b)));
+    paths
+}
+
+/// Keep only the paths found in every run.
+///
+/// Run the scan again after the target has moved, e.g. after restarting
+/// the process, and intersect the results to drop paths that only worked
+/// by chance.
+pub fn intersect_paths(runs: &[Vec<PointerPath>]) -> Vec<PointerPath> {
+    let mut runs = runs.iter();
+    let mut paths: BTreeSet<&PointerPath> = match runs.next() {
+        Some(first) => first.iter().collect(),
+        None => return vec![],
+    };
+    for run in runs {
+        let run: BTreeSet<&PointerPath> = run.iter().collect();
+        paths = paths.intersection(&run).cloned().collect();
+    }
+    paths.into_iter().cloned().collect()
+}
+
+struct PointerScanner<'a> {
+    // Every pointer found, as (value, address), sorted by value.
+    map: Vec<(u64, u64)>,
+    statics: &'a [StaticRegion],
+    options: &'a PointerScanOptions,
+    // Pointers followed so far.
+    visits: Cell<usize>,
+}
+
+impl<'a> PointerScanner<'a> {
+    // Look for pointers to just below `target`.  `offsets` holds the offsets
+    // from `target` to the scan's target, innermost first, and `visited` the
+    // addresses already on the path so pointer cycles aren't followed.
+    fn search(
+        &self,
+        target: u64,
+        offsets: &mut Vec<u32>,
+        visited: &mut Vec<u64>,
+        paths: &mut Vec<PointerPath>,
+    ) {
+        let low = target.saturating_sub(self.options.max_offset as u64);
+        let first = self.map.partition_point(|(value, _)| *value < low);
+        for (value, addr) in &self.map[first..] {
+            if *value > target
+                || paths.len() >= self.options.max_results
+                || self.visits.get() >= self.options.max_visits
+            {
+                break;
+            }
+            if visited.contains(addr) {
+                continue;
+            }
+            self.visits.set(self.visits.get() + 1);
+            offsets.push((target - value) as u32);
+            match self.static_offset(*addr) {
+                Some((module, module_offset)) => paths.push(PointerPath {
+                    module: module.to_string(),
+                    module_offset,
+                    offsets: offsets.iter().rev().cloned().collect(),
+                }),
+                None if offsets.len() < self.options.max_depth => {
+                    visited.push(*addr);
+                    self.search(*addr, offsets, visited, paths);
+                    visited.pop();
+                }
+                None => {}
+            }
+            offsets.pop();
+        }
+    }
+
+    fn static_offset(&self, addr: u64) -> Option<(&str, u32)> {
+        self.statics
+            .iter()
+            .find(|s| addr >= s.start_addr && addr < s.end_addr)
+            .and_then(|s| Some((s.module.as_str(), (addr - s.start_addr).try_into().ok()?)))
+    }
+}
+
+// Read every aligned u64 in `regions` that points into one of them.
+fn pointer_map(mem: &dyn MemReader, regions: &[(u64, u64)], align: u64) -> Vec<(u64, u64)> {
+    let is_pointer = |value: u64| {
+        regions
+            .iter()
+            .any(|(start_addr, end_addr)| value >= *start_addr && value < *end_addr)
+    };
+
+    let mut map = vec![];
+    for &(start_addr, end_addr) in regions {
+        let mut values = ValueReader::new(mem, start_addr, end_addr, 8, align);
+        while let Some((addr, bytes)) = values.next_value() {
+            let value = u64::from_ne_bytes(bytes.try_into().unwrap());
+            if is_pointer(value) {
+                map.push((value, addr));
+            }
+        }
+    }
+    map.sort_unstable();
+    map.dedup();
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::TestMemReader;
+    use super::*;
+
+    fn write_ptr(mem: &mut TestMemReader, addr: u64, value: u64) {
+        let offset = (addr - mem.start_addr) as usize;
+        mem.mem[offset..offset + 8].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    // The static data of game.exe points at an object that points at
+    // another object holding the target at `object + 0x8`.
+    fn get_mem_reader(object: u64, inner: u64) -> TestMemReader {
+        let mut mem = TestMemReader {
+            mem: vec![0x0; 0x300],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        mem.modules.insert("game.exe".to_string(), 0x1000);
+        write_ptr(&mut mem, 0x1010, object);
+        write_ptr(&mut mem, object + 0x18, inner);
+        mem
+    }
+
+    fn scan(mem: &TestMemReader, target: u64, max_depth: usize) -> Vec<PointerPath> {
+        let statics = [StaticRegion {
+            module: "game.exe".to_string(),
+            start_addr: 0x1000,
+            end_addr: 0x1100,
+        }];
+        let options = PointerScanOptions {
+            max_depth,
+            max_offset: 0x20,
+            ..Default::default()
+        };
+        pointer_scan(mem, target, &statics, &[(0x1100, 0x1300)], &options)
+    }
+
+    #[test]
+    fn find_paths() {
+        let mut mem = get_mem_reader(0x1200, 0x1280);
+        // A pointer that happens to be close to the target.
+        write_ptr(&mut mem, 0x1020, 0x1284);
+
+        let paths = scan(&mem, 0x1288, 4);
+        let ops: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            ops,
+            vec![
+                "module(\"game.exe\")+0x20; ptr(0); add(4)",
+                "module(\"game.exe\")+0x10; ptr(0); ptr(24); add(8)",
+            ]
+        );
+        for path in &paths {
+            assert_eq!(path.resolve(&mem), Some(0x1288));
+        }
+
+        assert_eq!(scan(&mem, 0x1288, 1).len(), 1);
+    }
+
+    #[test]
+    fn intersect_runs() {
+        let mut first = get_mem_reader(0x1200, 0x1280);
+        write_ptr(&mut first, 0x1020, 0x1284);
+        // After a restart the objects moved and the decoy points elsewhere.
+        let second = get_mem_reader(0x1240, 0x12c0);
+
+        let paths = intersect_paths(&[scan(&first, 0x1288, 4), scan(&second, 0x12c8, 4)]);
+        assert_eq!(
+            paths,
+            vec![PointerPath {
+                module: "game.exe".to_string(),
+                module_offset: 0x10,
+                offsets: vec![0x18, 0x8],
+            }]
+        );
+        assert_eq!(paths[0].resolve(&second), Some(0x12c8));
+        assert!(intersect_paths(&[]).is_empty());
+    }
+
+    #[test]
+    fn pointer_cycle() {
+        // The object and the inner one point at each other.
+        let mut mem = get_mem_reader(0x1200, 0x1280);
+        write_ptr(&mut mem, 0x1280, 0x1200);
+
+        // Going around the cycle would find ever longer paths.
+        let paths = scan(&mem, 0x1288, 8);
+        let ops: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            ops,
+            vec!["module(\"game.exe\")+0x10; ptr(0); ptr(24); add(8)"]
+        );
+    }
+
+    #[test]
+    fn dense_pointers() {
+        // Every pointer is within `max_offset` of every other one, and none
+        // are reachable from static data, so the search would walk every
+        // ordering of them up to `max_depth` without a budget.
+        let mut mem = get_mem_reader(0x1200, 0x1280);
+        write_ptr(&mut mem, 0x1010, 0x0);
+        for addr in (0x1100..0x1300).step_by(8) {
+            write_ptr(&mut mem, addr, 0x1100);
+        }
+
+        let statics = [StaticRegion {
+            module: "game.exe".to_string(),
+            start_addr: 0x1000,
+            end_addr: 0x1100,
+        }];
+        let options = PointerScanOptions {
+            max_depth: 32,
+            max_offset: 0x100,
+            ..Default::default()
+        };
+        let paths = pointer_scan(&mem, 0x1180, &statics, &[(0x1100, 0x1300)], &options);
+        assert!(paths.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use super::super::MemReader;
+use super::super::{Address, MemReader};
 use failure::{format_err, Error};
 use std::ffi::CStr;
 use std::mem::size_of;
@@ -144,6 +144,53 @@ impl Process {
         }
     }
 
+    /// Returns the base address of the loaded module called `name`.
+    ///
+    /// Module names are compared case insensitively.
+    pub fn module_base_addr(&self, name: &str) -> Option<LPVOID> {
+        unsafe {
+            let mut modules: Vec<HMODULE> = vec![std::ptr::null_mut(); 1024];
+            let mut cb_needed: DWORD = 0;
+            let success = psapi::EnumProcessModules(
+                self.handle,
+                modules.as_mut_ptr(),
+                (modules.len() * size_of::<HMODULE>()) as DWORD,
+                &mut cb_needed as *mut DWORD,
+            );
+            if success == FALSE {
+                return None;
+            }
+
+            let num_modules = cb_needed as usize / size_of::<HMODULE>();
+            for module in modules.iter().take(num_modules) {
+                let mut raw_name: Vec<i8> = vec![0; MAX_PATH];
+                psapi::GetModuleBaseNameA(
+                    self.handle,
+                    *module,
+                    raw_name.as_mut_ptr(),
+                    raw_name.len() as DWORD,
+                );
+                let module_name = CStr::from_ptr(raw_name.as_ptr()).to_string_lossy();
+                if !module_name.eq_ignore_ascii_case(name) {
+                    continue;
+                }
+
+                let mut info: psapi::MODULEINFO = Default::default();
+                let success = psapi::GetModuleInformation(
+                    self.handle,
+                    *module,
+                    &mut info as *mut psapi::MODULEINFO,
+                    size_of::<psapi::MODULEINFO>() as DWORD,
+                );
+                if success == FALSE {
+                    return None;
+                }
+                return Some(info.lpBaseOfDll);
+            }
+            None
+        }
+    }
+
     /// Load a cached copy of the process' BaseModule.
     ///
     /// This allows for much faster resolving of `Signature`s
@@ -178,15 +225,21 @@ impl Drop for Process {
 
 impl MemReader for Process {
     fn read(&self, buf: &mut [u8], addr: u64, len: usize) -> usize {
-        let base_addr = self.base_addr as u64;
-        if addr >= base_addr {
-            let start_index = (addr - base_addr) as usize;
-            let end_index = start_index + len - 1;
-            if end_index < self.base_contents.len() {
-                buf.copy_from_slice(&self.base_contents[start_index..=end_index]);
+        let len = len.min(buf.len());
+        if let Some(start_index) = Address(addr).offset_from(Address(self.base_addr as u64)) {
+            let start_index = start_index as usize;
+            let contents = start_index
+                .checked_add(len)
+                .and_then(|end_index| self.base_contents.get(start_index..end_index));
+            if let Some(contents) = contents {
+                buf[..len].copy_from_slice(contents);
                 return len;
             }
         }
         self.read_memory(buf, addr as LPVOID, len)
     }
+
+    fn module_base(&self, name: &str) -> Option<u64> {
+        self.module_base_addr(name).map(|addr| addr as u64)
+    }
 }
@@ -0,0 +1,885 @@
+use super::cache::fingerprint;
+use super::signature::Signature;
+use super::{ArrayConfig, FieldConfig};
+use super::{ArrayResolver, MemReader, Resolver, Scannable, SharedResolveCache, TypeConfig};
+use failure::{format_err, Error};
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+// Keys of a registry document that aren't type names.
+const INCLUDE_KEY: &str = "include";
+const VARIANTS_KEY: &str = "variants";
+const DEFAULT_KEY: &str = "default";
+
+/// The configs of one version of the target.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ConfigVariant {
+    /// Fingerprints, as returned by `cache::fingerprint`, of the module
+    /// builds the variant is for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fingerprints: Vec<String>,
+    pub types: BTreeMap<String, TypeConfig>,
+}
+
+/// The `TypeConfig`s of several types, keyed by type name.
+///
+/// A registry is read from a single json5 document mapping each type's name
+/// to its config:
+///
+/// ```text
+/// {
+///     Player: { signature: "...", fields: { hp: 0x10 } },
+///     Target: { signature: "...", fields: { id: 0x8 } },
+/// }
+/// ```
+///
+/// Configs that differ between versions of the target go in `variants`,
+/// keyed by version.  Once a variant is selected its types are used in place
+/// of the top level ones.  The `default` variant is selected when nothing
+/// else matches:
+///
+/// ```text
+/// {
+///     Target: { signature: "...", fields: { id: 0x8 } },
+///     variants: {
+///         "6.4": { fingerprints: ["pe:5f3a1b2c:2a4000"], types: { Player: { ... } } },
+///         "6.5": { fingerprints: ["pe:60aa0c11:2a5000"], types: { Player: { ... } } },
+///     },
+///     default: "6.5",
+/// }
+/// ```
+///
+/// A type can `extends` another to inherit its signature, array and fields,
+/// overriding any it sets itself.  A variant's type extending its own name
+/// inherits from the top level type.  Files listed in `include`, relative
+/// to the including file, are read first and overridden by the including
+/// file:
+///
+/// ```text
+/// {
+///     include: ["common.json5"],
+///     Player: { extends: "Actor", fields: { hp: 0x10 } },
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "RegistryFile")]
+pub struct ConfigRegistry {
+    types: BTreeMap<String, TypeConfig>,
+    variants: BTreeMap<String, ConfigVariant>,
+    default: Option<String>,
+    // The version of the selected variant.
+    version: Option<String>,
+}
+
+impl ConfigRegistry {
+    /// Read a json5 registry.  The default variant, if any, is selected.
+    ///
+    /// Use `load` for registries that include other files.
+    pub fn new(reader: &mut impl Read) -> Result<ConfigRegistry, Error> {
+        let mut buffer = String::new();
+        reader.read_to_string(&mut buffer)?;
+
+        Ok(json5::from_str(&buffer)?)
+    }
+
+    /// Read the json5 registry at `path` along with the files it includes.
+    pub fn load(path: impl AsRef<Path>) -> Result<ConfigRegistry, Error> {
+        let path = path.as_ref();
+        let path = path
+            .canonicalize()
+            .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        ConfigRegistry::try_from(RegistryFile::load(&path, &mut vec![])?)
+    }
+
+    /// Write the registry as json5.  The output can be read back with `new`.
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.write_all(json5::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Use `cache` when resolving the signature of every config.
+    pub fn with_cache(mut self, cache: SharedResolveCache) -> Self {
+        let variant_types = self.variants.values_mut().map(|v| &mut v.types);
+        for types in std::iter::once(&mut self.types).chain(variant_types) {
+            for config in types.values_mut() {
+                config.cache = Some(cache.clone());
+            }
+        }
+        self
+    }
+
+    /// Add or replace the top level config for `name`.
+    pub fn insert(&mut self, name: &str, config: TypeConfig) {
+        self.types.insert(name.to_string(), config);
+    }
+
+    /// Add or replace the variant for `version`.
+    pub fn insert_variant(&mut self, version: &str, variant: ConfigVariant) {
+        self.variants.insert(version.to_string(), variant);
+    }
+
+    /// Returns the config for `name` from the selected variant, falling back
+    /// to the top level configs.
+    pub fn get(&self, name: &str) -> Option<&TypeConfig> {
+        self.selected()
+            .and_then(|variant| variant.types.get(name))
+            .or_else(|| self.types.get(name))
+    }
+
+    /// The names of the types available with the selected variant, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        let variant_names = self.selected().into_iter().flat_map(|v| v.types.keys());
+        let names: BTreeSet<&String> = self.types.keys().chain(variant_names).collect();
+        names.into_iter().map(|name| name.as_str())
+    }
+
+    /// The versions of the registry's variants, in order.
+    pub fn versions(&self) -> impl Iterator<Item = &str> {
+        self.variants.keys().map(|version| version.as_str())
+    }
+
+    /// The version of the selected variant.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Select the variant for `version`.
+    pub fn select_version(&mut self, version: &str) -> Result<(), Error> {
+        if !self.variants.contains_key(version) {
+            return Err(format_err!(
+                "unknown config version {}; known versions: {}",
+                version,
+                self.versions().collect::<Vec<_>>().join(", ")
+            ));
+        }
+        self.version = Some(version.to_string());
+        Ok(())
+    }
+
+    /// Select the variant for the module between `start_addr` and `end_addr`
+    /// by its fingerprint, or the default variant if none lists it.
+    ///
+    /// Returns the selected version, or `None` if the registry has no
+    /// variants.
+    pub fn select(
+        &mut self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+    ) -> Result<Option<&str>, Error> {
+        if self.variants.is_empty() {
+            return Ok(None);
+        }
+
+        let fingerprint = fingerprint(mem, start_addr, end_addr);
+        let version = self
+            .variants
+            .iter()
+            .find(|(_, variant)| variant.fingerprints.contains(&fingerprint))
+            .map(|(version, _)| version.clone())
+            .or_else(|| self.default.clone());
+        match version {
+            Some(version) => self.version = Some(version),
+            None => {
+                let known: Vec<String> = self
+                    .variants
+                    .iter()
+                    .map(|(version, variant)| {
+                        format!("{} ({})", version, variant.fingerprints.join(", "))
+                    })
+                    .collect();
+                return Err(format_err!(
+                    "no config version for module {}; known versions: {}",
+                    fingerprint,
+                    known.join(", ")
+                ));
+            }
+        }
+        Ok(self.version())
+    }
+
+    /// Returns the config for `name` or an error listing the known types.
+    pub fn config(&self, name: &str) -> Result<&TypeConfig, Error> {
+        if let Some(config) = self.get(name) {
+            return Ok(config);
+        }
+        if self.version.is_none() && self.variants.values().any(|v| v.types.contains_key(name)) {
+            return Err(format_err!(
+                "no config version selected for type {}; known versions: {}",
+                name,
+                self.versions().collect::<Vec<_>>().join(", ")
+            ));
+        }
+        Err(format_err!(
+            "no config for type {}; known types: {}",
+            name,
+            self.names().collect::<Vec<_>>().join(", ")
+        ))
+    }
+
+    /// Returns a `Resolver` for `T` using the config named after `T`.
+    pub fn get_resolver_by_name<T: Scannable>(&self) -> Result<Box<Resolver<T>>, Error> {
+        T::get_resolver(self.config(T::type_name())?.clone())
+    }
+
+    /// Returns an `ArrayResolver` for `T` using the config named after `T`.
+    pub fn get_array_resolver_by_name<T: Scannable>(&self) -> Result<Box<ArrayResolver<T>>, Error> {
+        T::get_array_resolver(self.config(T::type_name())?.clone())
+    }
+
+    fn selected(&self) -> Option<&ConfigVariant> {
+        self.variants.get(self.version.as_ref()?)
+    }
+}
+
+impl Serialize for ConfigRegistry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for (name, config) in &self.types {
+            map.serialize_entry(name, config)?;
+        }
+        if !self.variants.is_empty() {
+            map.serialize_entry(VARIANTS_KEY, &self.variants)?;
+        }
+        if let Some(default) = &self.default {
+            map.serialize_entry(DEFAULT_KEY, default)?;
+        }
+        map.end()
+    }
+}
+
+// A registry document before its includes and `extends` are resolved.
+#[derive(Default)]
+struct RegistryFile {
+    include: Vec<String>,
+    types: BTreeMap<String, RawTypeConfig>,
+    variants: BTreeMap<String, RawVariant>,
+    default: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct RawVariant {
+    #[serde(default)]
+    fingerprints: Vec<String>,
+    #[serde(default)]
+    types: BTreeMap<String, RawTypeConfig>,
+}
+
+// A `TypeConfig` that may inherit from another type.
+#[derive(Deserialize)]
+struct RawTypeConfig {
+    #[serde(default)]
+    extends: Option<String>,
+    #[serde(default)]
+    signature: Option<Signature>,
+    #[serde(default)]
+    array: Option<ArrayConfig>,
+    #[serde(default)]
+    fields: HashMap<String, FieldConfig>,
+
+    // Where the config was read from, for errors.
+    #[serde(skip)]
+    file: Option<String>,
+    #[serde(skip)]
+    key: String,
+}
+
+impl RawTypeConfig {
+    fn error(&self, e: impl fmt::Display) -> Error {
+        match &self.file {
+            Some(file) => format_err!("{}: {}: {}", file, self.key, e),
+            None => format_err!("{}: {}", self.key, e),
+        }
+    }
+}
+
+impl RegistryFile {
+    // Read the file at the canonical `path`, and the files it includes.
+    // `stack` holds the files including it.
+    fn load(path: &Path, stack: &mut Vec<PathBuf>) -> Result<RegistryFile, Error> {
+        if let Some(i) = stack.iter().position(|p| p == path) {
+            let cycle: Vec<String> = stack[i..]
+                .iter()
+                .chain(std::iter::once(&path.to_path_buf()))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(format_err!("include cycle: {}", cycle.join(" -> ")));
+        }
+
+        let name = path.display().to_string();
+        let text = fs::read_to_string(path).map_err(|e| format_err!("{}: {}", name, e))?;
+        let mut file: RegistryFile =
+            json5::from_str(&text).map_err(|e| format_err!("{}: {}", name, e))?;
+        file.set_file(&name);
+
+        // Included files are overridden by later includes and by the file
+        // itself.
+        let mut merged = RegistryFile::default();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        stack.push(path.to_path_buf());
+        for include in std::mem::take(&mut file.include) {
+            let include_path = dir
+                .join(&include)
+                .canonicalize()
+                .map_err(|e| format_err!("{}: include {}: {}", name, include, e))?;
+            merged.merge(RegistryFile::load(&include_path, stack)?);
+        }
+        stack.pop();
+        merged.merge(file);
+        Ok(merged)
+    }
+
+    fn set_file(&mut self, file: &str) {
+        let variant_types = self
+            .variants
+            .values_mut()
+            .flat_map(|v| v.types.values_mut());
+        for config in self.types.values_mut().chain(variant_types) {
+            config.file = Some(file.to_string());
+        }
+    }
+
+    fn merge(&mut self, other: RegistryFile) {
+        self.types.extend(other.types);
+        for (version, other_variant) in other.variants {
+            let variant = self.variants.entry(version).or_default();
+            for fingerprint in other_variant.fingerprints {
+                if !variant.fingerprints.contains(&fingerprint) {
+                    variant.fingerprints.push(fingerprint);
+                }
+            }
+            variant.types.extend(other_variant.types);
+        }
+        self.default = other.default.or_else(|| self.default.take());
+    }
+}
+
+impl TryFrom<RegistryFile> for ConfigRegistry {
+    type Error = Error;
+
+    fn try_from(file: RegistryFile) -> Result<ConfigRegistry, Error> {
+        if !file.include.is_empty() {
+            return Err(format_err!(
+                "include is only supported by ConfigRegistry::load"
+            ));
+        }
+
+        let mut registry = ConfigRegistry::default();
+        for name in file.types.keys() {
+            let (config, _) = resolve_type(&[&file.types], name, 0, &mut vec![])?;
+            registry.types.insert(name.clone(), config);
+        }
+
+        // A variant's types are layered over the top level ones.  Top level
+        // types that inherit from one of the variant's types are added to
+        // the variant.
+        for (version, variant) in &file.variants {
+            let layers = [&variant.types, &file.types];
+            let mut types = BTreeMap::new();
+            for name in variant.types.keys().chain(file.types.keys()) {
+                let (config, in_variant) = resolve_type(&layers, name, 0, &mut vec![])?;
+                if in_variant {
+                    types.insert(name.clone(), config);
+                }
+            }
+            let fingerprints = variant.fingerprints.clone();
+            registry.variants.insert(
+                version.clone(),
+                ConfigVariant {
+                    fingerprints,
+                    types,
+                },
+            );
+        }
+
+        registry.default = file.default;
+        if let Some(default) = registry.default.clone() {
+            registry
+                .select_version(&default)
+                .map_err(|e| format_err!("bad default: {}", e))?;
+        }
+        Ok(registry)
+    }
+}
+
+// Find the type called `name` in the first of `layers`, from `start` on,
+// that has it.
+fn find_type<'a>(
+    layers: &[&'a BTreeMap<String, RawTypeConfig>],
+    name: &str,
+    start: usize,
+) -> Option<(usize, &'a RawTypeConfig)> {
+    layers
+        .iter()
+        .enumerate()
+        .skip(start)
+        .find_map(|(i, types)| types.get(name).map(|raw| (i, raw)))
+}
+
+// Resolve the type called `name`, looking it up from layer `start` on.
+// `stack` holds the layer and name of the types inheriting from it.
+//
+// Returns the config and whether it came from, or inherits from, the first
+// of several layers.
+fn resolve_type(
+    layers: &[&BTreeMap<String, RawTypeConfig>],
+    name: &str,
+    start: usize,
+    stack: &mut Vec<(usize, String)>,
+) -> Result<(TypeConfig, bool), Error> {
+    let (layer, raw) =
+        find_type(layers, name, start).ok_or_else(|| format_err!("unknown type {}", name))?;
+    let mut in_first = layers.len() > 1 && layer == 0;
+
+    let base = match &raw.extends {
+        Some(base) => {
+            // A type extending its own name inherits from the layers below.
+            let base_start = match base == name && layer + 1 < layers.len() {
+                true => layer + 1,
+                false => 0,
+            };
+            let base_layer = match find_type(layers, base, base_start) {
+                Some((base_layer, _)) => base_layer,
+                None => return Err(raw.error(format!("extends unknown type {}", base))),
+            };
+
+            stack.push((layer, name.to_string()));
+            if let Some(i) = stack
+                .iter()
+                .position(|(l, n)| *l == base_layer && n == base)
+            {
+                let cycle: Vec<&str> = stack[i..]
+                    .iter()
+                    .map(|(_, n)| n.as_str())
+                    .chain(std::iter::once(base.as_str()))
+                    .collect();
+                return Err(raw.error(format!("extends cycle: {}", cycle.join(" -> "))));
+            }
+            let base = resolve_type(layers, base, base_start, stack);
+            stack.pop();
+
+            let (base, base_in_first) = base?;
+            in_first |= base_in_first;
+            Some(base)
+        }
+        None => None,
+    };
+
+    let signature = match (&raw.signature, &base) {
+        (Some(signature), _) => signature.clone(),
+        (None, Some(base)) => base.signature.clone(),
+        (None, None) => return Err(raw.error("missing signature")),
+    };
+    let mut fields = base.as_ref().map(|b| b.fields.clone()).unwrap_or_default();
+    fields.extend(raw.fields.clone());
+    let config = TypeConfig {
+        signature,
+        array: raw.array.clone().or_else(|| base.and_then(|b| b.array)),
+        fields,
+        cache: None,
+    };
+    Ok((config, in_first))
+}
+
+impl<'de> Deserialize<'de> for RegistryFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(RegistryVisitor)
+    }
+}
+
+struct RegistryVisitor;
+
+impl<'de> Visitor<'de> for RegistryVisitor {
+    type Value = RegistryFile;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a map of type names to configs")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RegistryFile, A::Error> {
+        let mut file = RegistryFile::default();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                INCLUDE_KEY => file.include = map.next_value()?,
+                VARIANTS_KEY => file.variants = map.next_value()?,
+                DEFAULT_KEY => file.default = Some(map.next_value()?),
+                _ => {
+                    let mut config: RawTypeConfig = map
+                        .next_value()
+                        .map_err(|e| de::Error::custom(format!("{}: {}", key, e)))?;
+                    config.key = key.clone();
+                    file.types.insert(key, config);
+                }
+            }
+        }
+        for (version, variant) in file.variants.iter_mut() {
+            for (name, config) in variant.types.iter_mut() {
+                config.key = format!("{}.{}.types.{}", VARIANTS_KEY, version, name);
+            }
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::TestMemReader;
+    use super::*;
+
+    fn get_registry() -> ConfigRegistry {
+        let mut text = "
+        {
+            Player: {
+                signature: \"base+0x10\",
+                fields: { hp: 0x0 },
+            },
+            Target: {
+                signature: [\"asm(00112233^^^^^^^^)\", \"ptr(0)\"],
+                fields: { id: { offset: 0x8, type: \"u32\" } },
+            },
+        }"
+        .as_bytes();
+        ConfigRegistry::new(&mut text).unwrap()
+    }
+
+    fn get_versioned_registry(default: &str) -> Result<ConfigRegistry, Error> {
+        let text = format!(
+            "
+        {{
+            Target: {{ signature: \"base\", fields: {{ id: 0x8 }} }},
+            variants: {{
+                \"6.4\": {{
+                    fingerprints: [\"pe:5f3a1b2c:100\"],
+                    types: {{ Player: {{ signature: \"base+0x10\", fields: {{ hp: 0x0 }} }} }},
+                }},
+                \"6.5\": {{
+                    fingerprints: [\"pe:60aa0c11:100\"],
+                    types: {{ Player: {{ signature: \"base+0x20\", fields: {{ hp: 0x0 }} }} }},
+                }},
+            }},
+            {}
+        }}",
+            default
+        );
+        ConfigRegistry::new(&mut text.as_bytes())
+    }
+
+    // A PE image with the given link timestamp.
+    fn get_pe_mem_reader(timestamp: u32) -> TestMemReader {
+        let mut mem = vec![0x0; 0x100];
+        mem[0..2].copy_from_slice(b"MZ");
+        mem[0x3c] = 0x40;
+        mem[0x40..0x44].copy_from_slice(b"PE\0\0");
+        mem[0x48..0x4c].copy_from_slice(&timestamp.to_le_bytes());
+        mem[0x90..0x94].copy_from_slice(&0x100u32.to_le_bytes());
+        TestMemReader {
+            mem,
+            start_addr: 0x1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let registry = get_registry();
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            vec!["Player", "Target"]
+        );
+        assert_eq!(
+            registry.config("Player").unwrap().signature,
+            "base+0x10".parse().unwrap()
+        );
+        assert_eq!(
+            registry.config("Party").err().map(|e| e.to_string()),
+            Some("no config for type Party; known types: Player, Target".to_string())
+        );
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), Error> {
+        let mut registry = get_registry();
+        let mut text = "{ signature: \"base\", fields: {} }".as_bytes();
+        registry.insert("Party", TypeConfig::new(&mut text)?);
+
+        let mut buf = Vec::new();
+        registry.write(&mut buf)?;
+        let read_registry = ConfigRegistry::new(&mut buf.as_slice())?;
+
+        assert_eq!(
+            read_registry.names().collect::<Vec<_>>(),
+            vec!["Party", "Player", "Target"]
+        );
+        for name in registry.names() {
+            let (config, read_config) = (registry.config(name)?, read_registry.config(name)?);
+            assert_eq!(read_config.signature, config.signature);
+            assert_eq!(read_config.fields, config.fields);
+        }
+
+        let registry = get_versioned_registry("default: \"6.4\"")?;
+        let mut buf = Vec::new();
+        registry.write(&mut buf)?;
+        let read_registry = ConfigRegistry::new(&mut buf.as_slice())?;
+        assert_eq!(
+            read_registry.versions().collect::<Vec<_>>(),
+            vec!["6.4", "6.5"]
+        );
+        assert_eq!(read_registry.version(), Some("6.4"));
+        Ok(())
+    }
+
+    #[test]
+    fn versions() -> Result<(), Error> {
+        let player_signature = |registry: &ConfigRegistry| {
+            registry
+                .config("Player")
+                .map(|config| config.signature.to_string())
+        };
+
+        let mut registry = get_versioned_registry("")?;
+        assert_eq!(registry.version(), None);
+        assert_eq!(
+            player_signature(&registry).map_err(|e| e.to_string()),
+            Err("no config version selected for type Player; known versions: 6.4, 6.5".to_string())
+        );
+
+        let mem = get_pe_mem_reader(0x60aa_0c11);
+        assert_eq!(registry.select(&mem, 0x1000, 0x1100)?, Some("6.5"));
+        assert_eq!(player_signature(&registry)?, "base+0x20");
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            vec!["Player", "Target"]
+        );
+        assert_eq!(registry.config("Target")?.signature.to_string(), "base");
+
+        registry.select_version("6.4")?;
+        assert_eq!(player_signature(&registry)?, "base+0x10");
+        assert_eq!(
+            registry.select_version("7.0").map_err(|e| e.to_string()),
+            Err("unknown config version 7.0; known versions: 6.4, 6.5".to_string())
+        );
+
+        let unknown = get_pe_mem_reader(0x1234_5678);
+        assert_eq!(
+            registry
+                .select(&unknown, 0x1000, 0x1100)
+                .map_err(|e| e.to_string()),
+            Err(
+                "no config version for module pe:12345678:100; known versions: \
+                 6.4 (pe:5f3a1b2c:100), 6.5 (pe:60aa0c11:100)"
+                    .to_string()
+            )
+        );
+
+        // The default is used when no fingerprint matches.
+        let mut registry = get_versioned_registry("default: \"6.4\"")?;
+        assert_eq!(registry.version(), Some("6.4"));
+        assert_eq!(registry.select(&unknown, 0x1000, 0x1100)?, Some("6.4"));
+        assert_eq!(registry.select(&mem, 0x1000, 0x1100)?, Some("6.5"));
+
+        assert_eq!(
+            get_versioned_registry("default: \"6.6\"")
+                .map_err(|e| e.to_string())
+                .err(),
+            Some("bad default: unknown config version 6.6; known versions: 6.4, 6.5".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn extends() -> Result<(), Error> {
+        let mut text = "
+        {
+            Actor: {
+                signature: \"base+0x10\",
+                fields: { id: 0x0, pos: 0x8 },
+            },
+            Player: { extends: \"Actor\", fields: { pos: 0xc, hp: 0x20 } },
+            Boss: { extends: \"Player\", signature: \"base+0x30\" },
+            variants: {
+                \"6.4\": {
+                    types: { Actor: { extends: \"Actor\", fields: { id: 0x4 } } },
+                },
+            },
+        }"
+        .as_bytes();
+        let mut registry = ConfigRegistry::new(&mut text)?;
+        let offsets = |registry: &ConfigRegistry, name: &str| {
+            let config = registry.config(name).unwrap();
+            let mut fields: Vec<(String, u64)> = config
+                .fields
+                .iter()
+                .map(|(name, field)| (name.clone(), field.offset))
+                .collect();
+            fields.sort();
+            (config.signature.to_string(), fields)
+        };
+        let fields = |fields: &[(&str, u64)]| -> Vec<(String, u64)> {
+            fields.iter().map(|(n, o)| (n.to_string(), *o)).collect()
+        };
+
+        assert_eq!(
+            offsets(&registry, "Player"),
+            (
+                "base+0x10".to_string(),
+                fields(&[("hp", 0x20), ("id", 0x0), ("pos", 0xc)])
+            )
+        );
+        assert_eq!(offsets(&registry, "Boss").0, "base+0x30");
+
+        // The variant's base class change reaches the top level types.
+        registry.select_version("6.4")?;
+        assert_eq!(
+            offsets(&registry, "Boss"),
+            (
+                "base+0x30".to_string(),
+                fields(&[("hp", 0x20), ("id", 0x4), ("pos", 0xc)])
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn extends_errors() {
+        let error = |text: &str| {
+            ConfigRegistry::new(&mut text.as_bytes())
+                .err()
+                .map(|e| e.to_string())
+                .unwrap_or_default()
+        };
+
+        assert!(
+            error("{ A: { extends: \"B\" }, B: { extends: \"C\" }, C: { extends: \"A\" } }")
+                .ends_with("C: extends cycle: A -> B -> C -> A")
+        );
+        assert!(error("{ A: { extends: \"A\" } }").ends_with("A: extends cycle: A -> A"));
+        assert!(
+            error("{ A: { extends: \"B\", fields: {} } }").ends_with("A: extends unknown type B")
+        );
+        assert!(error("{ A: { fields: { x: 0x0 } } }").ends_with("A: missing signature"));
+        assert!(
+            error("{ variants: { \"1.0\": { types: { A: { extends: \"B\" } } } } }")
+                .ends_with("variants.1.0.types.A: extends unknown type B")
+        );
+        assert!(error("{ include: [\"a.json5\"] }")
+            .ends_with("include is only supported by ConfigRegistry::load"));
+    }
+
+    // A directory of config files that is removed when dropped.
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> ConfigDir {
+            let dir = std::env::temp_dir().join(format!(
+                "memscanner-registry-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::create_dir_all(dir.join("common")).unwrap();
+            for (file, text) in files {
+                fs::write(dir.join(file), text).unwrap();
+            }
+            ConfigDir(dir.canonicalize().unwrap())
+        }
+
+        fn load(&self, file: &str) -> Result<ConfigRegistry, String> {
+            ConfigRegistry::load(self.0.join(file)).map_err(|e| e.to_string())
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn include() -> Result<(), Error> {
+        let dir = ConfigDir::new(
+            "include",
+            &[
+                (
+                    "common/actor.json5",
+                    "{ Actor: { signature: \"base\", fields: { id: 0x0 } } }",
+                ),
+                (
+                    "common/items.json5",
+                    "{
+                        include: [\"actor.json5\"],
+                        Item: { extends: \"Actor\", fields: { count: 0x8 } },
+                        variants: { \"6.4\": { fingerprints: [\"pe:1\"], types: {} } },
+                    }",
+                ),
+                (
+                    "game.json5",
+                    "{
+                        include: [\"common/items.json5\"],
+                        Actor: { signature: \"base+0x10\", fields: { id: 0x4 } },
+                        variants: { \"6.4\": { fingerprints: [\"pe:2\"], types: {} } },
+                    }",
+                ),
+            ],
+        );
+
+        let registry = dir.load("game.json5").map_err(|e| format_err!("{}", e))?;
+        assert_eq!(registry.names().collect::<Vec<_>>(), vec!["Actor", "Item"]);
+        // Item inherits from the including file's Actor.
+        let item = registry.config("Item")?;
+        assert_eq!(item.signature.to_string(), "base+0x10");
+        assert_eq!(item.fields.get("id").map(|f| f.offset), Some(0x4));
+        assert_eq!(item.fields.get("count").map(|f| f.offset), Some(0x8));
+        assert_eq!(
+            registry.variants.get("6.4").map(|v| v.fingerprints.clone()),
+            Some(vec!["pe:1".to_string(), "pe:2".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn include_errors() {
+        let dir = ConfigDir::new(
+            "include_errors",
+            &[
+                ("a.json5", "{ include: [\"common/b.json5\"] }"),
+                ("common/b.json5", "{ include: [\"../a.json5\"] }"),
+                ("missing.json5", "{ include: [\"nope.json5\"] }"),
+                (
+                    "bad.json5",
+                    "{ include: [\"common/base.json5\"], B: { extends: \"C\" } }",
+                ),
+                ("common/base.json5", "{ A: { fields: {} } }"),
+            ],
+        );
+        let path = |file: &str| dir.0.join(file).display().to_string();
+
+        assert_eq!(
+            dir.load("a.json5").err(),
+            Some(format!(
+                "include cycle: {} -> {} -> {}",
+                path("a.json5"),
+                path("common/b.json5"),
+                path("a.json5")
+            ))
+        );
+        assert!(dir
+            .load("missing.json5")
+            .err()
+            .unwrap()
+            .starts_with(&format!("{}: include nope.json5: ", path("missing.json5"))));
+        // Errors point at the file that defines the type.
+        assert_eq!(
+            dir.load("bad.json5").err(),
+            Some(format!(
+                "{}: A: missing signature",
+                path("common/base.json5")
+            ))
+        );
+    }
+}
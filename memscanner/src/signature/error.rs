@@ -0,0 +1,183 @@
+use crate::AddressError;
+use failure::Fail;
+use nom::error::{VerboseError, VerboseErrorKind};
+use std::fmt;
+
+/// An error describing where and why a signature failed to parse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureParseError {
+    /// Index of the alternative op chain containing the error.
+    pub alternative: usize,
+    /// Index of the op within its chain.
+    pub op_index: usize,
+    /// 1-based byte column of the error within the parsed string.
+    pub column: usize,
+    /// What went wrong, usually naming the expected token.
+    pub message: String,
+}
+
+impl fmt::Display for SignatureParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at column {} (alternative {}, op {})",
+            self.message, self.column, self.alternative, self.op_index
+        )
+    }
+}
+
+impl Fail for SignatureParseError {}
+
+impl SignatureParseError {
+    // `rest` must be the unparsed tail of `input`.
+    pub(super) fn new(
+        input: &str,
+        rest: &str,
+        alternative: usize,
+        op_index: usize,
+        message: &str,
+    ) -> Self {
+        SignatureParseError {
+            alternative,
+            op_index,
+            column: input.len() - rest.len() + 1,
+            message: message.to_string(),
+        }
+    }
+
+    // Build an error from the trail left by a failed parse of `input`.  The
+    // first entry is where parsing stopped and the first context is the most
+    // specific description of what was expected there.
+    pub(super) fn from_nom(
+        input: &str,
+        err: nom::Err<VerboseError<&str>>,
+        alternative: usize,
+        op_index: usize,
+    ) -> Self {
+        let errors = match err {
+            nom::Err::Error(e) | nom::Err::Failure(e) => e.errors,
+            nom::Err::Incomplete(_) => vec![],
+        };
+        let rest = errors.first().map(|(i, _)| *i).unwrap_or("");
+        let message = errors
+            .iter()
+            .find_map(|(_, kind)| match kind {
+                VerboseErrorKind::Context(c) => Some(*c),
+                _ => None,
+            })
+            .unwrap_or("syntax error");
+        SignatureParseError::new(input, rest, alternative, op_index, message)
+    }
+}
+
+/// Why a single op failed to resolve.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResolveErrorKind {
+    /// Nothing between `start_addr` and `end_addr` matched the pattern.
+    NoMatch { start_addr: u64, end_addr: u64 },
+    /// The string was found but no `lea` references its address `target`.
+    NoReference { target: u64 },
+    /// The pointer at `addr` could not be read.
+    UnreadablePointer { addr: u64 },
+    /// Only `read` of the `len` bytes at `addr` could be read.
+    ShortRead { addr: u64, len: usize, read: usize },
+    /// The instruction at `addr` is not a `call rel32` or `jmp rel32`.
+    NotABranch { addr: u64, opcode: u8 },
+    /// No instruction with a displacement or branch target at `addr`
+    /// could be decoded.
+    NoOperand { addr: u64 },
+    /// The `MemReader` does not know about the named module.
+    UnknownModule(String),
+    /// An offset left the address space or produced a non-canonical
+    /// address.
+    BadAddress(AddressError),
+}
+
+impl From<AddressError> for ResolveErrorKind {
+    fn from(e: AddressError) -> Self {
+        ResolveErrorKind::BadAddress(e)
+    }
+}
+
+impl fmt::Display for ResolveErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolveErrorKind::NoMatch {
+                start_addr,
+                end_addr,
+            } => write!(f, "no match in 0x{:x}..0x{:x}", start_addr, end_addr),
+            ResolveErrorKind::NoReference { target } => {
+                write!(f, "no lea references 0x{:x}", target)
+            }
+            ResolveErrorKind::UnreadablePointer { addr } => {
+                write!(f, "can't read pointer at 0x{:x}", addr)
+            }
+            ResolveErrorKind::ShortRead { addr, len, read } => {
+                write!(f, "short read at 0x{:x}: {} of {} bytes", addr, read, len)
+            }
+            ResolveErrorKind::NotABranch { addr, opcode } => write!(
+                f,
+                "opcode 0x{:02x} at 0x{:x} is not a call or jmp",
+                opcode, addr
+            ),
+            ResolveErrorKind::NoOperand { addr } => {
+                write!(f, "no instruction operand at 0x{:x}", addr)
+            }
+            ResolveErrorKind::UnknownModule(name) => write!(f, "unknown module \"{}\"", name),
+            ResolveErrorKind::BadAddress(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// An error describing which op of a `Signature` failed to resolve and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolveError {
+    /// Index of the alternative op chain that failed.
+    pub alternative: usize,
+    /// Index of the failing op within its chain.
+    pub op_index: usize,
+    /// The failing op in signature syntax.
+    pub op: String,
+    /// The address reached before the failing op.
+    pub addr: u64,
+    pub kind: ResolveErrorKind,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} failed at 0x{:x} (alternative {}, op {}): {}",
+            self.op, self.addr, self.alternative, self.op_index, self.kind
+        )
+    }
+}
+
+impl Fail for ResolveError {}
+
+/// One step of a traced `Signature` resolution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    pub alternative: usize,
+    pub op_index: usize,
+    /// The op in signature syntax.
+    pub op: String,
+    /// The address before the op was applied.
+    pub addr: u64,
+    /// The address after the op was applied, or why it failed.
+    pub result: Result<u64, ResolveErrorKind>,
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{}.{}] {} @ 0x{:x} -> ",
+            self.alternative, self.op_index, self.op, self.addr
+        )?;
+        match &self.result {
+            Ok(addr) => write!(f, "0x{:x}", addr),
+            Err(e) => write!(f, "{}", e),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use super::{check_pattern, find_matches, AsmOp, Decode, Match, Op, Signature, MAX_INSN_LEN};
+use crate::MemReader;
+use failure::{format_err, Error};
+
+/// The longest pattern `generate_signature` will try before giving up.
+pub const MAX_GENERATED_LEN: usize = 64;
+
+/// Generate the shortest `asm()` signature that uniquely identifies the
+/// instruction at `insn_addr` between `start_addr` and `end_addr`.
+///
+/// If `data_addr` is given, the instruction must reference it with a
+/// RIP-relative displacement and the signature resolves to `data_addr`.
+/// Otherwise the signature resolves to `insn_addr` itself.
+///
+/// Displacements, branch targets and 64 bit immediates that point into
+/// mapped memory are wildcarded since they change whenever the binary is
+/// rebuilt.
+pub fn generate_signature(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    insn_addr: u64,
+    data_addr: Option<u64>,
+) -> Result<Signature, Error> {
+    if insn_addr < start_addr || insn_addr >= end_addr {
+        return Err(format_err!(
+            "0x{:x} is outside of 0x{:x}..0x{:x}",
+            insn_addr,
+            start_addr,
+            end_addr
+        ));
+    }
+
+    let max_len = MAX_GENERATED_LEN.min((end_addr - insn_addr) as usize);
+    let mut bytes = vec![0x0; max_len];
+    let len = mem.read(&mut bytes, insn_addr, max_len);
+    bytes.truncate(len);
+
+    let mut pattern: Vec<Match> = bytes.iter().cloned().map(Match::Literal).collect();
+    let (decode, min_len) = match data_addr {
+        Some(data_addr) => {
+            let (disp_offset, insn_end) = find_displacement(&bytes, insn_addr, data_addr)
+                .ok_or_else(|| {
+                    format_err!(
+                        "instruction at 0x{:x} does not reference 0x{:x}",
+                        insn_addr,
+                        data_addr
+                    )
+                })?;
+            for m in &mut pattern[disp_offset..disp_offset + 4] {
+                *m = Match::Position;
+            }
+            let decode = Decode::Rip {
+                disp_size: 4,
+                insn_end,
+            };
+            (decode, disp_offset + 4)
+        }
+        None => (Decode::Raw, 1),
+    };
+    if bytes.len() < min_len {
+        return Err(format_err!("0x{:x} is not readable", insn_addr));
+    }
+    wildcard_relocations(mem, start_addr, end_addr, insn_addr, &bytes, &mut pattern);
+
+    // Every location matching the shortest pattern is a candidate.  Each
+    // byte added to the pattern can only remove candidates.
+    let first = search_pattern(&pattern[..min_len]);
+    let mut candidates: Vec<u64> = find_matches(mem, start_addr, end_addr, &first, 1)
+        .into_iter()
+        .map(|match_end| match_end - min_len as u64)
+        .collect();
+
+    for len in min_len..=pattern.len() {
+        // A trailing wildcard can't make the pattern more unique.
+        if pattern[len - 1] == Match::Any {
+            continue;
+        }
+        let search = search_pattern(&pattern[..len]);
+        candidates.retain(|addr| {
+            check_pattern(mem, *addr, end_addr, &search)
+                .ok()
+                .flatten()
+                .is_some()
+        });
+        if candidates == [insn_addr] {
+            return Ok(build_signature(pattern[..len].to_vec(), decode));
+        }
+    }
+
+    Err(format_err!(
+        "no unique signature for 0x{:x} within {} bytes",
+        insn_addr,
+        pattern.len()
+    ))
+}
+
+// Build the signature for `pattern`.  Raw patterns have no position so the
+// match resolves to their end, which is then moved back to the instruction.
+fn build_signature(pattern: Vec<Match>, decode: Decode) -> Signature {
+    let len = pattern.len() as i32;
+    let raw = decode == Decode::Raw;
+    let mut ops = vec![Op::Asm(AsmOp {
+        decode,
+        ..AsmOp::new(pattern)
+    })];
+    if raw {
+        ops.push(Op::Add(-len));
+    }
+    Signature {
+        alternatives: vec![ops],
+    }
+}
+
+// Replace position markers with plain wildcards so a match returns its end.
+fn search_pattern(pattern: &[Match]) -> Vec<Match> {
+    pattern
+        .iter()
+        .map(|m| match m {
+            Match::Position => Match::Any,
+            m => m.clone(),
+        })
+        .collect()
+}
+
+// Arbitrary bytes read as a displacement may point anywhere, so wrap rather
+// than overflow.
+fn rel_target(addr: u64, rel: i32) -> u64 {
+    addr.wrapping_add(rel as i64 as u64)
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> Option<i32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// Find the RIP-relative displacement in the instruction at the start of
+// `bytes` that refers to `data_addr`.  Returns its offset and the distance
+// from it to the end of the instruction, allowing for a trailing immediate.
+fn find_displacement(bytes: &[u8], insn_addr: u64, data_addr: u64) -> Option<(usize, u32)> {
+    for offset in 1..=(MAX_INSN_LEN - 4) {
+        let disp = read_i32(bytes, offset)?;
+        for imm_size in &[0, 1, 2, 4] {
+            let insn_end = 4 + imm_size;
+            let disp_addr = insn_addr + offset as u64;
+            if rel_target(disp_addr, disp).wrapping_add(insn_end as u64) == data_addr {
+                return Some((offset, insn_end));
+            }
+        }
+    }
+    None
+}
+
+// Wildcard the bytes of `pattern` that are likely to move between builds:
+// rel32 call, jmp and jcc targets inside the code range, RIP-relative
+// displacements pointing at mapped memory and `mov r64, imm64` addresses.
+//
+// Without a disassembler this is a heuristic and may wildcard a few bytes
+// that would have been stable.
+fn wildcard_relocations(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    insn_addr: u64,
+    bytes: &[u8],
+    pattern: &mut [Match],
+) {
+    let in_code = |addr: u64| addr >= start_addr && addr < end_addr;
+    let rel32_target = |offset: usize| {
+        read_i32(bytes, offset).map(|rel| rel_target(insn_addr + offset as u64 + 4, rel))
+    };
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if pattern[i] != Match::Literal(bytes[i]) {
+            i += 1;
+            continue;
+        }
+        let next = bytes.get(i + 1).cloned();
+        let wild = match (bytes[i], next) {
+            (0xe8, _) | (0xe9, _) if matches!(rel32_target(i + 1), Some(t) if in_code(t)) => {
+                Some((i + 1, 4))
+            }
+            (0x0f, Some(0x80..=0x8f)) if matches!(rel32_target(i + 2), Some(t) if in_code(t)) => {
+                Some((i + 2, 4))
+            }
+            (0x48..=0x4f, Some(0xb8..=0xbf)) => bytes
+                .get(i + 2..i + 10)
+                .map(|b| {
+                    let mut imm = [0x0; 8];
+                    imm.copy_from_slice(b);
+                    u64::from_le_bytes(imm)
+                })
+                .filter(|imm| mem.is_mapped(*imm))
+                .map(|_| (i + 2, 8)),
+            // A ModRM byte selecting [rip+disp32].
+            (modrm, _) if i > 0 && (modrm & 0xc7) == 0x05 => rel32_target(i + 1)
+                .filter(|addr| mem.is_mapped(*addr))
+                .map(|_| (i + 1, 4)),
+            _ => None,
+        };
+        match wild {
+            Some((offset, len)) => {
+                let end = (offset + len).min(pattern.len());
+                // Don't clobber an already placed position.
+                for m in &mut pattern[offset..end] {
+                    if *m != Match::Position {
+                        *m = Match::Any;
+                    }
+                }
+                i = end;
+            }
+            None => i += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::test::TestMemReader;
+    use super::*;
+
+    fn get_code_mem_reader() -> TestMemReader {
+        #[rustfmt::skip]
+        let r = TestMemReader {
+            mem: vec![
+                // mov rcx, [rip+0x31]
+                0x48, 0x8b, 0x0d, 0x31, 0x00, 0x00, 0x00,
+                // call 0x1020
+                0xe8, 0x14, 0x00, 0x00, 0x00,
+                // mov rcx, [rip+0x1d]
+                0x48, 0x8b, 0x0d, 0x1d, 0x00, 0x00, 0x00,
+                // test ecx, ecx
+                0x85, 0xc9,
+                // call 0x1020
+                0xe8, 0x06, 0x00, 0x00, 0x00,
+                0xc3, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc,
+                // 0x1020
+                0xc3, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc,
+                0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc,
+                // 0x1030
+                0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        r
+    }
+
+    #[test]
+    fn generate_data_reference() -> Result<(), Error> {
+        let mem = get_code_mem_reader();
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let sig = generate_signature(&mem, mem.start_addr, end_addr, 0x100c, Some(0x1030))?;
+        assert_eq!(sig.to_string(), "asm(488b0d^^^^^^^^85)");
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1030));
+
+        assert!(generate_signature(&mem, mem.start_addr, end_addr, 0x100c, Some(0x1038)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn generate_raw() -> Result<(), Error> {
+        let mem = get_code_mem_reader();
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        // The call target and the following displacement are wildcarded.
+        let sig = generate_signature(&mem, mem.start_addr, end_addr, 0x1007, None)?;
+        assert_eq!(sig.to_string(), "asm(e8********48, raw); add(-6)");
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1007));
+
+        let sig = generate_signature(&mem, mem.start_addr, end_addr, 0x1020, None)?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1020));
+        Ok(())
+    }
+
+    #[test]
+    fn generate_not_unique() {
+        let mem = TestMemReader {
+            mem: vec![0x90; 0x100],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+        assert!(generate_signature(&mem, mem.start_addr, end_addr, 0x1010, None).is_err());
+        assert!(generate_signature(&mem, mem.start_addr, end_addr, 0x2000, None).is_err());
+
+        // In range but past the end of readable memory.
+        let err = generate_signature(&mem, mem.start_addr, 0x2000, 0x1800, None).unwrap_err();
+        assert_eq!(err.to_string(), "0x1800 is not readable");
+    }
+}
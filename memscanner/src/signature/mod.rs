@@ -1,152 +1,1078 @@
+mod error;
+mod generate;
 mod parser;
+#[cfg(feature = "x86")]
+mod x86;
 
-use super::MemReader;
+pub use error::{ResolveError, ResolveErrorKind, SignatureParseError, TraceStep};
+pub use generate::{generate_signature, MAX_GENERATED_LEN};
+
+use super::{Address, MemReader};
 use failure::{format_err, Error};
+use nom::combinator::all_consuming;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+// The longest x86 instruction.
+const MAX_INSN_LEN: usize = 15;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum Match {
     Any,
     Position,
     Literal(u8),
+    // Between `min` and `max` arbitrary bytes.
+    Skip { min: u32, max: u32 },
+}
+
+// The encoding of the string searched for by an `xref()` op.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Encoding {
+    Ascii,
+    Utf16,
+}
+
+impl Encoding {
+    fn char_size(&self) -> usize {
+        match self {
+            Encoding::Ascii => 1,
+            Encoding::Utf16 => 2,
+        }
+    }
+}
+
+// How the location matched by an `asm()` op is turned into an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Decode {
+    // The position holds a RIP-relative displacement `disp_size` bytes wide.
+    // The instruction ends `insn_end` bytes after the start of the
+    // displacement.
+    Rip {
+        disp_size: u8,
+        insn_end: u32,
+    },
+    // The position holds the RIP-relative displacement or rel32 branch
+    // target of an instruction, which is decoded to find where it ends.
+    #[cfg(feature = "x86")]
+    Operand,
+    // The matched position is the result.
+    Raw,
+}
+
+impl Default for Decode {
+    fn default() -> Self {
+        Decode::Rip {
+            disp_size: 4,
+            insn_end: 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AsmOp {
+    pattern: Vec<Match>,
+    decode: Decode,
+    // When set, only search `scope` bytes starting at the address produced
+    // by the previous op instead of the whole range.
+    scope: Option<u32>,
+    // How many literal bytes may differ from memory.  When no exact match
+    // exists, the match with the fewest differences is used.
+    fuzzy: u32,
+    // Only addresses that are a multiple of `align` are tested as the start
+    // of a match.
+    align: u32,
+}
+
+impl AsmOp {
+    fn new(pattern: Vec<Match>) -> AsmOp {
+        AsmOp {
+            pattern,
+            decode: Default::default(),
+            scope: None,
+            fuzzy: 0,
+            align: 1,
+        }
+    }
+}
+
+/// A literal byte of a pattern that differs from memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub addr: u64,
+    pub expected: u8,
+    pub found: u8,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:x}: expected {:02x}, found {:02x}",
+            self.addr, self.expected, self.found
+        )
+    }
+}
+
+/// A location matched by a pattern with some of its literal bytes allowed to
+/// differ.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    /// The start of the match.
+    pub match_addr: u64,
+    /// The matched position, as `Signature::matches` reports it.
+    pub addr: u64,
+    /// The differing bytes in address order.  Fewer is a better match.
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl fmt::Display for FuzzyMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let n = self.mismatches.len();
+        write!(f, "0x{:x}: {} mismatches", self.match_addr, n)?;
+        if n > 0 {
+            let mismatches: Vec<String> = self.mismatches.iter().map(|m| m.to_string()).collect();
+            write!(f, " ({})", mismatches.join("; "))?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum Op {
-    Asm(Vec<Match>),
+    Asm(AsmOp),
     Ptr(i32),
+    Ptr32(i32),
+    Add(i32),
+    Follow,
+    // A static offset from the named module's base address, or from the
+    // start of the scanned range when no module is given.
+    Module(Option<String>, i64),
+    // The `lea` instruction that references a string.
+    Xref(String, Encoding),
+    // A sequence of instructions matched by decoding them.
+    #[cfg(feature = "x86")]
+    Insn(x86::InsnOp),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// A description of how to find an address in memory.
+///
+/// The textual form is a `;` separated chain of ops.  Alternative chains are
+/// separated by `|`:
+///
+/// ```text
+/// asm(488b0d^^^^^^^^); ptr(0) | xref("Player"); asm(488d0d^^^^^^^^, scope=0x40)
+/// ```
+///
+/// With the `x86` feature, `insn(mov rcx, [rip+^]; call ?)` searches for
+/// decoded instructions rather than bytes, and `asm(c705^^^^^^^^, end=auto)`
+/// decodes the matched instruction to find where it ends.
+///
+/// In configs a `Signature` is a list of op strings, or a list of such lists
+/// when there are alternatives.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "SignatureRepr", into = "SignatureRepr")]
 pub struct Signature {
-    ops: Vec<Op>,
+    // Op chains that are tried in order until one of them resolves.
+    alternatives: Vec<Vec<Op>>,
 }
 
-impl Signature {
-    pub fn new(ops: &Vec<String>) -> Result<Signature, Error> {
-        let mut sig = Signature { ops: vec![] };
+// The forms a `Signature` can take in a config.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum SignatureRepr {
+    Text(String),
+    Single(Vec<String>),
+    Alternatives(Vec<Vec<String>>),
+}
 
-        for op_str in ops {
-            let (_, op) =
-                parser::parse_op(op_str).map_err(|_| format_err!("Can't parse op: {}", op_str))?;
-            sig.ops.push(op);
+impl TryFrom<SignatureRepr> for Signature {
+    type Error = Error;
+
+    fn try_from(repr: SignatureRepr) -> Result<Signature, Error> {
+        match repr {
+            SignatureRepr::Text(text) => text.parse(),
+            SignatureRepr::Single(ops) => Signature::new(&ops),
+            SignatureRepr::Alternatives(alts) => Signature::with_alternatives(&alts),
+        }
+    }
+}
+
+impl From<Signature> for SignatureRepr {
+    fn from(sig: Signature) -> SignatureRepr {
+        let mut alts: Vec<Vec<String>> = sig
+            .alternatives
+            .iter()
+            .map(|ops| ops.iter().map(|op| op.to_string()).collect())
+            .collect();
+        match alts.len() {
+            1 => SignatureRepr::Single(alts.remove(0)),
+            _ => SignatureRepr::Alternatives(alts),
         }
+    }
+}
 
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, ops) in self.alternatives.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            for (j, op) in ops.iter().enumerate() {
+                if j > 0 {
+                    write!(f, "; ")?;
+                }
+                write!(f, "{}", op)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Signature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Signature, Error> {
+        let mut alternatives = vec![];
+        let mut ops = vec![];
+        let mut rest = s.trim_start();
+        loop {
+            let (r, op) = parser::parse_op(rest)
+                .map_err(|e| SignatureParseError::from_nom(s, e, alternatives.len(), ops.len()))?;
+            ops.push(op);
+
+            let r = r.trim_start();
+            if r.is_empty() {
+                alternatives.push(ops);
+                break;
+            } else if let Some(r) = r.strip_prefix(';') {
+                rest = r.trim_start();
+            } else if let Some(r) = r.strip_prefix('|') {
+                alternatives.push(std::mem::take(&mut ops));
+                rest = r.trim_start();
+            } else {
+                return Err(SignatureParseError::new(
+                    s,
+                    r,
+                    alternatives.len(),
+                    ops.len() - 1,
+                    "expected `;`, `|` or the end of the signature",
+                )
+                .into());
+            }
+        }
+        Ok(Signature { alternatives })
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Asm(a) => {
+                write!(f, "asm({}", format_pattern(&a.pattern))?;
+                match a.decode {
+                    Decode::Raw => write!(f, ", raw")?,
+                    #[cfg(feature = "x86")]
+                    Decode::Operand => write!(f, ", end=auto")?,
+                    Decode::Rip {
+                        disp_size,
+                        insn_end,
+                    } => {
+                        if disp_size != 4 {
+                            write!(f, ", disp={}", disp_size)?;
+                        }
+                        if insn_end != disp_size as u32 {
+                            write!(f, ", end={}", insn_end)?;
+                        }
+                    }
+                }
+                if let Some(scope) = a.scope {
+                    write!(f, ", scope=0x{:x}", scope)?;
+                }
+                if a.fuzzy > 0 {
+                    write!(f, ", fuzzy={}", a.fuzzy)?;
+                }
+                if a.align > 1 {
+                    write!(f, ", align={}", a.align)?;
+                }
+                write!(f, ")")
+            }
+            Op::Ptr(o) => write!(f, "ptr({})", o),
+            Op::Ptr32(o) => write!(f, "ptr32({})", o),
+            Op::Add(o) => write!(f, "add({})", o),
+            Op::Follow => write!(f, "follow()"),
+            Op::Module(name, o) => {
+                match name {
+                    Some(name) => write!(f, "module(\"{}\")", name)?,
+                    None => write!(f, "base")?,
+                }
+                match *o {
+                    0 => Ok(()),
+                    o if o < 0 => write!(f, "-0x{:x}", -o),
+                    o => write!(f, "+0x{:x}", o),
+                }
+            }
+            Op::Xref(text, Encoding::Ascii) => write!(f, "xref(\"{}\")", text),
+            Op::Xref(text, Encoding::Utf16) => write!(f, "xref(\"{}\", utf16)", text),
+            #[cfg(feature = "x86")]
+            Op::Insn(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+impl Signature {
+    pub fn new(ops: &[String]) -> Result<Signature, Error> {
+        Ok(Signature {
+            alternatives: vec![parse_ops(ops, 0)?],
+        })
+    }
+
+    /// Create a `Signature` from several alternative op chains.
+    ///
+    /// The alternatives are tried in order when resolving.  This lets a
+    /// single config cover several builds of a target.
+    pub fn with_alternatives(alternatives: &[Vec<String>]) -> Result<Signature, Error> {
+        if alternatives.is_empty() {
+            return Err(format_err!("Signature has no alternatives"));
+        }
+        let mut sig = Signature {
+            alternatives: vec![],
+        };
+        for (i, ops) in alternatives.iter().enumerate() {
+            sig.alternatives.push(parse_ops(ops, i)?);
+        }
         Ok(sig)
     }
 
-    pub fn resolve(&self, mem: &dyn MemReader, start_addr: u64, end_addr: u64) -> Option<u64> {
-        let mut addr = start_addr;
-        for op in &self.ops {
-            addr = match &op {
-                Op::Asm(p) => resolve_asm(mem, start_addr, end_addr, &p)?,
-                Op::Ptr(o) => resolve_ptr(mem, addr, *o)?,
-            };
+    pub fn resolve(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+    ) -> Result<u64, ResolveError> {
+        self.resolve_alternative(mem, start_addr, end_addr)
+            .map(|(_, addr)| addr)
+    }
+
+    /// Resolve the signature, returning the index of the alternative that
+    /// matched along with the address.
+    ///
+    /// If no alternative resolves, the error of the first one is returned.
+    pub fn resolve_alternative(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+    ) -> Result<(usize, u64), ResolveError> {
+        self.resolve_impl(mem, start_addr, end_addr, None)
+            .map(|(i, _, addr)| (i, addr))
+    }
+
+    /// Like `resolve_alternative` but also returns every step taken, which
+    /// is useful when debugging a config.
+    pub fn resolve_traced(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+    ) -> (Result<(usize, u64), ResolveError>, Vec<TraceStep>) {
+        let mut trace = vec![];
+        let result = self
+            .resolve_impl(mem, start_addr, end_addr, Some(&mut trace))
+            .map(|(i, _, addr)| (i, addr));
+        (result, trace)
+    }
+
+    // The number of leading ops of `alternative` whose result only depends
+    // on where the scanned range is loaded.  It ends before the first op
+    // that reads a pointer, whose value can change from run to run, or
+    // that uses the base of another module, which is relocated separately.
+    pub(crate) fn module_relative_len(&self, alternative: usize) -> usize {
+        let ops = &self.alternatives[alternative];
+        ops.iter()
+            .position(|op| matches!(op, Op::Ptr(_) | Op::Ptr32(_) | Op::Module(Some(_), _)))
+            .unwrap_or(ops.len())
+    }
+
+    // Like `resolve_alternative` but also returns the address reached by the
+    // first `module_relative_len` ops of the alternative that matched.
+    pub(crate) fn resolve_split(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+    ) -> Result<(usize, u64, u64), ResolveError> {
+        self.resolve_impl(mem, start_addr, end_addr, None)
+    }
+
+    // Run the ops of `alternative` after its first `module_relative_len`
+    // ops, starting at `addr`.  Returns `None` if there is no such
+    // alternative.
+    pub(crate) fn resume(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+        alternative: usize,
+        addr: u64,
+    ) -> Option<Result<u64, ResolveError>> {
+        let ops = self.alternatives.get(alternative)?;
+        let split = self.module_relative_len(alternative);
+        Some(resolve_ops(
+            mem,
+            start_addr,
+            end_addr,
+            ops.iter().enumerate().skip(split),
+            alternative,
+            addr,
+            None,
+        ))
+    }
+
+    /// Find every location matched by the first op of `alternative`.
+    ///
+    /// Returns `None` if that op is not an unscoped `asm()` search.  Each
+    /// address is the matched position, before any displacement decoding.
+    pub fn matches(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+        alternative: usize,
+    ) -> Option<Vec<u64>> {
+        match self.alternatives.get(alternative)?.first()? {
+            Op::Asm(op) if op.scope.is_none() => Some(find_matches(
+                mem,
+                start_addr,
+                end_addr,
+                &op.pattern,
+                op.align,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Find every location matched by the first op of `alternative` with up
+    /// to `max_mismatches` literal bytes differing, best match first.
+    ///
+    /// This is meant as a hint for updating a signature that stopped
+    /// matching after a patch.  Returns `None` if the op is not an unscoped
+    /// `asm()` search.
+    pub fn fuzzy_matches(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+        alternative: usize,
+        max_mismatches: u32,
+    ) -> Option<Vec<FuzzyMatch>> {
+        match self.alternatives.get(alternative)?.first()? {
+            Op::Asm(op) if op.scope.is_none() => Some(
+                find_fuzzy_matches(
+                    mem,
+                    start_addr,
+                    end_addr,
+                    &op.pattern,
+                    op.align,
+                    max_mismatches,
+                )
+                .unwrap_or_default(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn resolve_impl(
+        &self,
+        mem: &dyn MemReader,
+        start_addr: u64,
+        end_addr: u64,
+        mut trace: Option<&mut Vec<TraceStep>>,
+    ) -> Result<(usize, u64, u64), ResolveError> {
+        let mut first_err = None;
+        for (i, ops) in self.alternatives.iter().enumerate() {
+            let split = self.module_relative_len(i);
+            let result = resolve_ops(
+                mem,
+                start_addr,
+                end_addr,
+                ops[..split].iter().enumerate(),
+                i,
+                start_addr,
+                trace.as_deref_mut(),
+            )
+            .and_then(|prefix| {
+                let addr = resolve_ops(
+                    mem,
+                    start_addr,
+                    end_addr,
+                    ops.iter().enumerate().skip(split),
+                    i,
+                    prefix,
+                    trace.as_deref_mut(),
+                )?;
+                Ok((i, prefix, addr))
+            });
+            match result {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
         }
-        Some(addr)
+        // `with_alternatives` guarantees there is at least one alternative.
+        Err(first_err.unwrap())
     }
 }
 
-// Offset a u64 address by and i32 offset.  Takes care to not lose the upper
-// bit of the u64.  Does not handle over/underflow.
-fn offset_addr(addr: u64, offset: i32) -> u64 {
-    if offset < 0 {
-        addr - (-offset) as u64
-    } else {
-        addr + offset as u64
+fn parse_ops(ops: &[String], alternative: usize) -> Result<Vec<Op>, SignatureParseError> {
+    let mut parsed = vec![];
+    for (i, op_str) in ops.iter().enumerate() {
+        let (rest, op) = parser::parse_op(op_str)
+            .map_err(|e| SignatureParseError::from_nom(op_str, e, alternative, i))?;
+        if !rest.is_empty() {
+            return Err(SignatureParseError::new(
+                op_str,
+                rest,
+                alternative,
+                i,
+                "unexpected input after op",
+            ));
+        }
+        parsed.push(op);
     }
+    Ok(parsed)
 }
 
-// Check if `pattern` matches the contents of `mem` at `start_addr`
-fn check_pattern(
+// Apply `ops`, given with their indices in the alternative, starting at
+// `addr`.
+fn resolve_ops<'a>(
     mem: &dyn MemReader,
     start_addr: u64,
     end_addr: u64,
-    pattern: &[Match],
-) -> Option<u64> {
-    if end_addr - start_addr < pattern.len() as u64 {
-        // TODO: plumb Results through this whole thing.
-        println!("Not enough room for pattern");
-        return None;
+    ops: impl Iterator<Item = (usize, &'a Op)>,
+    alternative: usize,
+    mut addr: u64,
+    mut trace: Option<&mut Vec<TraceStep>>,
+) -> Result<u64, ResolveError> {
+    for (i, op) in ops {
+        let result = resolve_op(mem, start_addr, end_addr, addr, op);
+        if let Some(trace) = trace.as_mut() {
+            trace.push(TraceStep {
+                alternative,
+                op_index: i,
+                op: op.to_string(),
+                addr,
+                result: result.clone(),
+            });
+        }
+        addr = result.map_err(|kind| ResolveError {
+            alternative,
+            op_index: i,
+            op: op.to_string(),
+            addr,
+            kind,
+        })?;
     }
+    Ok(addr)
+}
 
-    // Read all the necessary bytes for the patter.
-    let mut mem_contents = vec![0x0; pattern.len()];
-    let mem_read = mem.read(&mut mem_contents, start_addr, pattern.len());
-    if mem_read != pattern.len() {
-        println!("incomplete read");
-        return None;
+// Apply a single `op` to the current address `addr`.
+fn resolve_op(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    addr: u64,
+    op: &Op,
+) -> Result<u64, ResolveErrorKind> {
+    match op {
+        Op::Asm(a) => match a.scope {
+            Some(len) => resolve_asm(mem, addr, Address(addr).advance(len as u64)?.0, a),
+            None => resolve_asm(mem, start_addr, end_addr, a),
+        },
+        Op::Ptr(o) => resolve_ptr(mem, addr, *o),
+        Op::Ptr32(o) => resolve_ptr32(mem, addr, *o),
+        Op::Add(o) => offset_addr(addr, *o as i64),
+        Op::Follow => resolve_follow(mem, addr),
+        Op::Module(name, o) => resolve_module(mem, start_addr, name, *o),
+        Op::Xref(text, e) => resolve_xref(mem, start_addr, end_addr, text, e),
+        #[cfg(feature = "x86")]
+        Op::Insn(op) => x86::resolve_insn(mem, start_addr, end_addr, op),
+    }
+}
+
+/// Converts an IDA/x64dbg style pattern (`48 8B 0D ?? ?? ?? ??`) into the
+/// compact form accepted by `asm()` (`488b0d********`).
+///
+/// `^` or `^^` may be used in place of a wildcard to mark the position.
+pub fn pattern_from_ida(ida: &str) -> Result<String, Error> {
+    let (_, pattern) = all_consuming(parser::parse_ida_pattern)(ida)
+        .map_err(|_| format_err!("Can't parse IDA pattern: {}", ida))?;
+    Ok(format_pattern(&pattern))
+}
+
+/// Converts a compact `asm()` pattern (`488b0d********`) into the space
+/// separated IDA/x64dbg style (`48 8B 0D ?? ?? ?? ??`).
+pub fn pattern_to_ida(pattern: &str) -> Result<String, Error> {
+    let (_, pattern) = all_consuming(parser::parse_pattern)(pattern)
+        .map_err(|_| format_err!("Can't parse pattern: {}", pattern))?;
+    Ok(format_ida_pattern(&pattern))
+}
+
+fn format_pattern(pattern: &[Match]) -> String {
+    pattern
+        .iter()
+        .map(|m| match m {
+            Match::Any => "**".to_string(),
+            Match::Position => "^^".to_string(),
+            Match::Literal(val) => format!("{:02x}", val),
+            Match::Skip { min, max } => format!("**{{{},{}}}", min, max),
+        })
+        .collect()
+}
+
+fn format_ida_pattern(pattern: &[Match]) -> String {
+    pattern
+        .iter()
+        .map(|m| match m {
+            Match::Any => "??".to_string(),
+            Match::Position => "^^".to_string(),
+            Match::Literal(val) => format!("{:02X}", val),
+            Match::Skip { min, max } => format!("??{{{},{}}}", min, max),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Offset `addr` by `offset`, failing if the result over or underflows or
+// isn't a canonical address.
+fn offset_addr(addr: u64, offset: i64) -> Result<u64, ResolveErrorKind> {
+    Ok(Address(addr).offset(offset)?.canonical()?.0)
+}
+
+// Read exactly `len` bytes at `addr`.
+fn read_exact(mem: &dyn MemReader, addr: u64, len: usize) -> Result<Vec<u8>, ResolveErrorKind> {
+    let mut buf = vec![0x0; len];
+    let read = mem.read(&mut buf, addr, len);
+    if read != len {
+        return Err(ResolveErrorKind::ShortRead { addr, len, read });
     }
+    Ok(buf)
+}
+
+// The fewest and most bytes `pattern` can match.
+fn pattern_len(pattern: &[Match]) -> (usize, usize) {
+    pattern.iter().fold((0, 0), |(min, max), m| match m {
+        Match::Skip { min: a, max: b } => (min + *a as usize, max + *b as usize),
+        _ => (min + 1, max + 1),
+    })
+}
+
+// Where a pattern matched, relative to the start of the match.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct BytesMatch {
+    len: usize,
+    // The offset of the first position token.
+    position: Option<usize>,
+    // The offset, expected and found value of each differing literal byte.
+    mismatches: Vec<(usize, u8, u8)>,
+}
 
-    // Determine if mem_contents matches the pattern.
-    let mut offset: Option<u64> = None;
-    for i in 0..pattern.len() {
-        match &pattern[i] {
+// Match `pattern` against `bytes` starting at `m.len`, allowing at most
+// `max_mismatches` literal bytes to differ.  Skips try their shortest length
+// first and backtrack to longer ones if the rest of the pattern doesn't
+// match, or matches with mismatches that a longer skip might avoid.
+fn match_bytes(
+    bytes: &[u8],
+    pattern: &[Match],
+    mut m: BytesMatch,
+    max_mismatches: usize,
+) -> Option<BytesMatch> {
+    for (i, token) in pattern.iter().enumerate() {
+        if let Match::Skip { min, max } = token {
+            let mut best: Option<BytesMatch> = None;
+            for n in *min..=*max {
+                // Only look for matches with fewer mismatches than the best.
+                let budget = best
+                    .as_ref()
+                    .map_or(max_mismatches, |b| b.mismatches.len() - 1);
+                let next = BytesMatch {
+                    len: m.len + n as usize,
+                    ..m.clone()
+                };
+                if let Some(found) = match_bytes(bytes, &pattern[i + 1..], next, budget) {
+                    // Nothing can beat a match with no more mismatches than
+                    // the bytes before the skip.
+                    let done = found.mismatches.len() == m.mismatches.len();
+                    best = Some(found);
+                    if done {
+                        break;
+                    }
+                }
+            }
+            return best;
+        }
+        let b = bytes.get(m.len)?;
+        match token {
             Match::Position => {
                 // Store the offset of the first match token.
-                if offset == None {
-                    offset = Some(i as u64);
-                }
+                m.position.get_or_insert(m.len);
             }
-            Match::Any => {}
-            Match::Literal(val) => {
-                if mem_contents[i] != *val {
+            Match::Literal(val) if b != val => {
+                if m.mismatches.len() == max_mismatches {
                     return None;
                 }
+                m.mismatches.push((m.len, *val, *b));
             }
-        };
+            _ => {}
+        }
+        m.len += 1;
     }
+    if m.len > bytes.len() {
+        return None;
+    }
+    Some(m)
+}
 
-    // If there were no position tokens, return the end of the match.
-    match offset {
-        None => Some(pattern.len() as u64),
-        Some(_) => offset,
+// Check if `pattern` matches the contents of `mem` at `start_addr` without
+// reading past `end_addr`.
+//
+// Returns the length of the match and the offset of the first position
+// token.
+fn check_pattern(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    pattern: &[Match],
+) -> Result<Option<(u64, Option<u64>)>, ResolveErrorKind> {
+    Ok(check_pattern_fuzzy(mem, start_addr, end_addr, pattern, 0)?
+        .map(|m| (m.len as u64, m.position.map(|p| p as u64))))
+}
+
+// Like `check_pattern` but allows up to `max_mismatches` literal bytes to
+// differ.  Returns the differing bytes along with the match.
+fn check_pattern_fuzzy(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    pattern: &[Match],
+    max_mismatches: u32,
+) -> Result<Option<BytesMatch>, ResolveErrorKind> {
+    // Read all the bytes the pattern could need.
+    let (min_len, max_len) = pattern_len(pattern);
+    let len = max_len.min(end_addr.saturating_sub(start_addr) as usize);
+    let mut mem_contents = vec![0x0; len];
+    let read = mem.read(&mut mem_contents, start_addr, len);
+    if read < min_len {
+        return Err(ResolveErrorKind::ShortRead {
+            addr: start_addr,
+            len,
+            read,
+        });
     }
+    mem_contents.truncate(read);
+
+    Ok(match_bytes(
+        &mem_contents,
+        pattern,
+        BytesMatch::default(),
+        max_mismatches as usize,
+    ))
 }
 
 // Scan through `mem` from `start_addr` to `end_addr` looking for a
 // pattern match.
+//
+// Returns the address of the match and of its first position token, or the
+// end of the match if there are none.
+//
+// Only matches starting at a multiple of `align` are considered.  Unreadable
+// locations are skipped.  If nothing matched, the first failed read is
+// reported since it may have hidden the match.
+fn find_match(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    pattern: &[Match],
+    align: u32,
+) -> Result<(u64, u64), ResolveErrorKind> {
+    let no_match = ResolveErrorKind::NoMatch {
+        start_addr,
+        end_addr,
+    };
+    let (min_len, _) = pattern_len(pattern);
+    let mem_len = end_addr.saturating_sub(start_addr);
+    if mem_len < min_len as u64 {
+        return Err(no_match);
+    }
+
+    let mut read_err = None;
+    for i in aligned_offsets(start_addr, mem_len - min_len as u64, align) {
+        let match_addr = start_addr + i;
+        match check_pattern(mem, match_addr, end_addr, pattern) {
+            Ok(Some((len, position))) => {
+                return Ok((match_addr, match_addr + position.unwrap_or(len)));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                read_err.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(read_err.unwrap_or(no_match))
+}
+
+// The offsets from `start_addr`, up to and including `max_offset`, of the
+// addresses that are a multiple of `align`.
+fn aligned_offsets(start_addr: u64, max_offset: u64, align: u32) -> impl Iterator<Item = u64> {
+    let align = align.max(1) as u64;
+    let first = start_addr.wrapping_neg() % align;
+    (first..=max_offset).step_by(align as usize)
+}
+
+// Scan through `mem` from `start_addr` to `end_addr` looking for a
+// pattern match.  Returns the address of its first position token, or the
+// end of the match if there are none.
 fn resolve_match(
     mem: &dyn MemReader,
     start_addr: u64,
     end_addr: u64,
     pattern: &[Match],
-) -> Option<u64> {
-    let mem_len = end_addr - start_addr;
-    for i in 0..=(mem_len as usize - pattern.len()) {
-        if let Some(offset) = check_pattern(mem, start_addr + i as u64, end_addr, pattern) {
-            return Some(start_addr + offset + i as u64);
+    align: u32,
+) -> Result<u64, ResolveErrorKind> {
+    find_match(mem, start_addr, end_addr, pattern, align).map(|(_, addr)| addr)
+}
+
+// Find every match of `pattern` between `start_addr` and `end_addr`.  Each
+// is reported as `resolve_match` would.
+fn find_matches(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    pattern: &[Match],
+    align: u32,
+) -> Vec<u64> {
+    let mut matches = vec![];
+    let mut search_addr = start_addr;
+    while let Ok((match_addr, addr)) = find_match(mem, search_addr, end_addr, pattern, align) {
+        matches.push(addr);
+        search_addr = match_addr + 1;
+    }
+    matches
+}
+
+// Find every location between `start_addr` and `end_addr` that matches
+// `pattern` with at most `max_mismatches` differing literal bytes, sorted by
+// the number of differences and then by address.
+//
+// Fails like `find_match` if there are none.
+fn find_fuzzy_matches(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    pattern: &[Match],
+    align: u32,
+    max_mismatches: u32,
+) -> Result<Vec<FuzzyMatch>, ResolveErrorKind> {
+    let no_match = ResolveErrorKind::NoMatch {
+        start_addr,
+        end_addr,
+    };
+    let (min_len, _) = pattern_len(pattern);
+    let mem_len = end_addr.saturating_sub(start_addr);
+    if mem_len < min_len as u64 {
+        return Err(no_match);
+    }
+
+    let mut matches = vec![];
+    let mut read_err = None;
+    for i in aligned_offsets(start_addr, mem_len - min_len as u64, align) {
+        let match_addr = start_addr + i;
+        match check_pattern_fuzzy(mem, match_addr, end_addr, pattern, max_mismatches) {
+            Ok(Some(m)) => matches.push(FuzzyMatch {
+                match_addr,
+                addr: match_addr + m.position.unwrap_or(m.len) as u64,
+                mismatches: m
+                    .mismatches
+                    .iter()
+                    .map(|(offset, expected, found)| Mismatch {
+                        addr: match_addr + *offset as u64,
+                        expected: *expected,
+                        found: *found,
+                    })
+                    .collect(),
+            }),
+            Ok(None) => {}
+            Err(e) => {
+                read_err.get_or_insert(e);
+            }
         }
     }
 
-    None
+    if matches.is_empty() {
+        return Err(read_err.unwrap_or(no_match));
+    }
+    // The sort is stable so equally good matches stay in address order.
+    matches.sort_by_key(|m| m.mismatches.len());
+    Ok(matches)
 }
 
 // Scan through `mem` from `start_addr` to `end_addr` looking for a
-// pattern match.  Then treat it as an argument to an indirect load holding
-// a pointer. Look up that location an return its contents.
+// pattern match.  Then, unless the op asks for the raw match, treat it as
+// the RIP-relative displacement of an instruction and return the address it
+// refers to.
 fn resolve_asm(
     mem: &dyn MemReader,
     start_addr: u64,
     end_addr: u64,
-    pattern: &[Match],
-) -> Option<u64> {
-    let match_addr = resolve_match(mem, start_addr, end_addr, pattern)?;
-    let offset = mem.read_i32(match_addr)?;
-    let addr = offset_addr(match_addr, offset) + 4;
-    Some(addr)
+    op: &AsmOp,
+) -> Result<u64, ResolveErrorKind> {
+    let match_addr = match resolve_match(mem, start_addr, end_addr, &op.pattern, op.align) {
+        // Fall back to the closest match, but report the exact search's
+        // error if there is none.
+        Err(e) if op.fuzzy > 0 => {
+            find_fuzzy_matches(mem, start_addr, end_addr, &op.pattern, op.align, op.fuzzy)
+                .map_err(|_| e)?[0]
+                .addr
+        }
+        result => result?,
+    };
+    decode_match(mem, match_addr, &op.decode)
+}
+
+// Turn a matched position into an address according to `decode`.
+fn decode_match(
+    mem: &dyn MemReader,
+    match_addr: u64,
+    decode: &Decode,
+) -> Result<u64, ResolveErrorKind> {
+    match *decode {
+        Decode::Raw => Ok(match_addr),
+        #[cfg(feature = "x86")]
+        Decode::Operand => x86::decode_operand(mem, match_addr)
+            .ok_or(ResolveErrorKind::NoOperand { addr: match_addr }),
+        Decode::Rip {
+            disp_size,
+            insn_end,
+        } => {
+            let disp = read_exact(mem, match_addr, disp_size as usize)?;
+            let offset = match disp_size {
+                1 => disp[0] as i8 as i32,
+                2 => i16::from_le_bytes([disp[0], disp[1]]) as i32,
+                _ => i32::from_le_bytes([disp[0], disp[1], disp[2], disp[3]]),
+            };
+            offset_addr(match_addr, offset as i64 + insn_end as i64)
+        }
+    }
+}
+
+// Find `text` in `mem` and then the `lea reg, [rip+disp32]` that loads its
+// address.  Returns the address of the `lea` instruction.
+fn resolve_xref(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    text: &str,
+    encoding: &Encoding,
+) -> Result<u64, ResolveErrorKind> {
+    let mut bytes: Vec<u8> = match encoding {
+        Encoding::Ascii => text.bytes().collect(),
+        Encoding::Utf16 => text
+            .encode_utf16()
+            .flat_map(|c| c.to_le_bytes().to_vec())
+            .collect(),
+    };
+    // Include the terminator so we don't match a prefix of a longer string.
+    bytes.resize(bytes.len() + encoding.char_size(), 0x0);
+    let pattern: Vec<Match> = bytes.into_iter().map(Match::Literal).collect();
+    let text_addr = resolve_match(mem, start_addr, end_addr, &pattern, 1)? - pattern.len() as u64;
+
+    // REX.W 8D /r with a RIP-relative ModRM.
+    let lea_pattern = vec![
+        Match::Any,
+        Match::Literal(0x8d),
+        Match::Any,
+        Match::Position,
+        Match::Any,
+        Match::Any,
+        Match::Any,
+    ];
+    let mut search_addr = start_addr;
+    while let Ok(match_addr) = resolve_match(mem, search_addr, end_addr, &lea_pattern, 1) {
+        let insn_addr = match_addr - 3;
+        let insn = read_exact(mem, insn_addr, 3)?;
+        let (rex, modrm) = (insn[0], insn[2]);
+        if (rex & 0xfb) == 0x48
+            && (modrm & 0xc7) == 0x05
+            && decode_match(mem, match_addr, &Decode::default()) == Ok(text_addr)
+        {
+            return Ok(insn_addr);
+        }
+        search_addr = insn_addr + 1;
+    }
+    Err(ResolveErrorKind::NoReference { target: text_addr })
 }
 
 // Look up the contents of `addr` (offset by `offset`) and return its contents.
-fn resolve_ptr(mem: &dyn MemReader, addr: u64, offset: i32) -> Option<u64> {
-    let addr = (addr as i64 + offset as i64) as u64;
-    let addr = mem.read_u64(addr)?;
-    Some(addr)
+fn resolve_ptr(mem: &dyn MemReader, addr: u64, offset: i32) -> Result<u64, ResolveErrorKind> {
+    let addr = offset_addr(addr, offset as i64)?;
+    mem.read_u64(addr)
+        .ok_or(ResolveErrorKind::UnreadablePointer { addr })
+}
+
+// Look up the 32 bit contents of `addr` (offset by `offset`) and return them
+// zero extended.
+fn resolve_ptr32(mem: &dyn MemReader, addr: u64, offset: i32) -> Result<u64, ResolveErrorKind> {
+    let addr = offset_addr(addr, offset as i64)?;
+    let val = mem
+        .read_u32(addr)
+        .ok_or(ResolveErrorKind::UnreadablePointer { addr })?;
+    Ok(val as u64)
+}
+
+// Offset the base address of module `name` (or `start_addr` if there is no
+// name) by `offset`.
+fn resolve_module(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    name: &Option<String>,
+    offset: i64,
+) -> Result<u64, ResolveErrorKind> {
+    let base = match name {
+        Some(name) => mem
+            .module_base(name)
+            .ok_or_else(|| ResolveErrorKind::UnknownModule(name.clone()))?,
+        None => start_addr,
+    };
+    offset_addr(base, offset)
+}
+
+// Decode the `call rel32` or `jmp rel32` at `addr` and return its target.
+fn resolve_follow(mem: &dyn MemReader, addr: u64) -> Result<u64, ResolveErrorKind> {
+    // The decoder also understands short and conditional branches.
+    #[cfg(feature = "x86")]
+    {
+        if let Some(target) = x86::branch_target(mem, addr) {
+            return Ok(target);
+        }
+    }
+    let insn = read_exact(mem, addr, 5)?;
+    match insn[0] {
+        0xe8 | 0xe9 => {
+            let offset = i32::from_le_bytes([insn[1], insn[2], insn[3], insn[4]]);
+            offset_addr(addr, 5 + offset as i64)
+        }
+        opcode => Err(ResolveErrorKind::NotABranch { addr, opcode }),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::test::TestMemReader;
     use super::*;
+    use crate::AddressError;
 
     #[test]
     fn single_lea() {
@@ -158,9 +1084,10 @@ mod tests {
                 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
             ],
             start_addr: 0x1000,
+            ..Default::default()
         };
 
-        let sig = Signature::new(&vec!["asm(00112233^^^^^^^^********)".to_string()]).unwrap();
+        let sig = Signature::new(&["asm(00112233^^^^^^^^********)".to_string()]).unwrap();
         println!("{:?}", sig);
         let offset = sig
             .resolve(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)
@@ -168,4 +1095,634 @@ mod tests {
         assert_eq!(offset, 0x1010);
         assert_eq!(mem.read_u64(offset).unwrap(), 0xffeeddccbbaa9988);
     }
+
+    #[test]
+    fn rip_decode() {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                // cmp byte ptr [rip+0x8], 0x1
+                0x80, 0x3d, 0x08, 0x00, 0x00, 0x00, 0x01, 0x00,
+                // mov dword ptr [rip+0x2], 0x55
+                0xc7, 0x05, 0x02, 0x00, 0x00, 0x00, 0x55, 0x00,
+                0x00, 0x00, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let sig = Signature::new(&["asm(803d^^^^^^^^01, end=5)".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x100f));
+
+        let sig = Signature::new(&["asm(c705^^^^^^^^55000000, end=8)".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1014));
+
+        let sig = Signature::new(&["asm(c705****^^**55, disp=1, end=6)".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1012));
+
+        let sig = Signature::new(&["asm(c705^^, raw)".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x100a));
+    }
+
+    #[test]
+    fn follow_call() {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                // call +0x3
+                0xe8, 0x03, 0x00, 0x00, 0x00, 0xcc, 0xcc, 0xcc,
+                // callee: lea rcx, [rip+0x1]
+                0x48, 0x8d, 0x0d, 0x01, 0x00, 0x00, 0x00, 0xc3,
+                0x18, 0x10, 0x00, 0x00, 0x20, 0x10, 0x00, 0x00,
+                0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let sig = Signature::new(&[
+            "asm(^^********cccccc, raw)".to_string(),
+            "follow()".to_string(),
+            "add(3)".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x100b));
+
+        let sig = Signature::new(&[
+            "asm(488d0d^^^^^^^^c3)".to_string(),
+            "ptr32(0x0)".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1018));
+
+        let sig = Signature::new(&[
+            "asm(488d0d^^^^^^^^c3)".to_string(),
+            "ptr32(4)".to_string(),
+            "add(-0x8)".to_string(),
+            "ptr(0)".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr),
+            Ok(0xffeeddccbbaa9988)
+        );
+
+        // `follow()` requires a call or jmp.
+        let sig = Signature::new(&[
+            "asm(488d0d^^^^^^^^c3, raw)".to_string(),
+            "follow()".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .unwrap_err()
+                .kind,
+            ResolveErrorKind::NotABranch {
+                addr: 0x100b,
+                opcode: 0x01
+            }
+        );
+    }
+
+    #[test]
+    fn scoped_asm() {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                // A decoy lea before the function we want.
+                0x48, 0x8d, 0x0d, 0x00, 0x00, 0x00, 0x00, 0xc3,
+                // call +0xb
+                0xe8, 0x0b, 0x00, 0x00, 0x00, 0xcc, 0xcc, 0xcc,
+                0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc,
+                // callee: lea rcx, [rip+0x10]
+                0x48, 0x8d, 0x0d, 0x10, 0x00, 0x00, 0x00, 0xc3,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let unscoped = Signature::new(&[
+            "asm(e8^^******cc, raw)".to_string(),
+            "add(-1)".to_string(),
+            "follow()".to_string(),
+            "asm(488d0d^^^^^^^^c3)".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(unscoped.resolve(&mem, mem.start_addr, end_addr), Ok(0x1007));
+
+        let scoped = Signature::new(&[
+            "asm(e8^^******cc, raw)".to_string(),
+            "add(-1)".to_string(),
+            "follow()".to_string(),
+            "asm(488d0d^^^^^^^^c3, scope=0x8)".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(scoped.resolve(&mem, mem.start_addr, end_addr), Ok(0x102f));
+
+        // The pattern doesn't fit in the window.
+        let too_small = Signature::new(&[
+            "asm(e8^^******cc, raw)".to_string(),
+            "add(-1)".to_string(),
+            "follow()".to_string(),
+            "asm(488d0d^^^^^^^^c3, scope=4)".to_string(),
+        ])
+        .unwrap();
+        let e = too_small
+            .resolve(&mem, mem.start_addr, end_addr)
+            .unwrap_err();
+        assert_eq!((e.op_index, e.addr), (3, 0x1018));
+        assert_eq!(
+            e.kind,
+            ResolveErrorKind::NoMatch {
+                start_addr: 0x1018,
+                end_addr: 0x101c
+            }
+        );
+    }
+
+    #[test]
+    fn module_offset() {
+        #[rustfmt::skip]
+        let mut mem = TestMemReader {
+            mem: vec![
+                0xff, 0xff, 0xff, 0xff, 0x00, 0x11, 0x22, 0x33,
+                0x18, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        mem.modules.insert("game.exe".to_string(), 0x1000);
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let sig = Signature::new(&["module(\"game.exe\")+0x8".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1008));
+
+        let sig = Signature::new(&[
+            "base+0x8".to_string(),
+            "ptr(0)".to_string(),
+            "add(-8)".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1010));
+
+        let sig = Signature::new(&["base".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1000));
+
+        let sig = Signature::new(&["module(\"other.dll\")".to_string()]).unwrap();
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .unwrap_err()
+                .kind,
+            ResolveErrorKind::UnknownModule("other.dll".to_string())
+        );
+
+        // Offsets that leave the address space fail rather than wrap.
+        let sig: Signature = "base-0x2000".parse().unwrap();
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .unwrap_err()
+                .to_string(),
+            "base-0x2000 failed at 0x1000 (alternative 0, op 0): 0x1000 - 0x2000 underflows"
+        );
+
+        // 0xffeeddccbbaa9988 isn't canonical.
+        let sig: Signature = "base+0x10; ptr(0); add(8)".parse().unwrap();
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .unwrap_err()
+                .kind,
+            ResolveErrorKind::BadAddress(AddressError::NonCanonical(0xffeeddccbbaa9990))
+        );
+    }
+
+    #[test]
+    fn string_xref() {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                // lea rdx, [rip+0x19] (points at "bad")
+                0x48, 0x8d, 0x15, 0x19, 0x00, 0x00, 0x00, 0xcc,
+                // lea r8, [rip+0x1] (points at "bad value")
+                0x4c, 0x8d, 0x05, 0x01, 0x00, 0x00, 0x00, 0xcc,
+                // "bad value"
+                0x62, 0x61, 0x64, 0x20, 0x76, 0x61, 0x6c, 0x75,
+                0x65, 0x00, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc,
+                // "bad"
+                0x62, 0x61, 0x64, 0x00, 0xcc, 0xcc, 0xcc, 0xcc,
+                // L"ok"
+                0x6f, 0x00, 0x6b, 0x00, 0x00, 0x00, 0xcc, 0xcc,
+                // lea rcx, [rip-0xf] (points at L"ok")
+                0x48, 0x8d, 0x0d, 0xf1, 0xff, 0xff, 0xff, 0xcc,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let sig = Signature::new(&["xref(\"bad value\")".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1008));
+
+        let sig = Signature::new(&["xref(\"bad\")".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1000));
+
+        let sig = Signature::new(&["xref(\"ok\", utf16)".to_string()]).unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1030));
+
+        let sig = Signature::new(&["xref(\"ok\")".to_string()]).unwrap();
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .unwrap_err()
+                .kind,
+            ResolveErrorKind::NoMatch {
+                start_addr: 0x1000,
+                end_addr
+            }
+        );
+
+        // The chain continues from the lea.
+        let sig = Signature::new(&[
+            "xref(\"bad value\")".to_string(),
+            "asm(4c8d05^^^^^^^^, scope=7)".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1010));
+    }
+
+    #[test]
+    fn variable_wildcard() {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                0xaa, 0x11, 0xbb, 0x22, 0xbb, 0xcc, 0x04, 0x00,
+                0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xaa, 0xbb,
+                0xcc, 0xbb,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        // The one byte gap matches `bb` but not `cc` so the matcher has to
+        // backtrack to a three byte gap.
+        let sig: Signature = "asm(aa**{1,4}bbcc^^, raw)".parse().unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1006));
+
+        let sig: Signature = "asm(aa**{1,4}bbcc^^^^^^^^)".parse().unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x100e));
+
+        let sig: Signature = "asm(^^**{0,2}cc, raw)".parse().unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1002));
+
+        let sig: Signature = "asm(aa**{0,1}cc, raw)".parse().unwrap();
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1011));
+
+        let sig: Signature = "asm(aa**{1,4}bb, raw)".parse().unwrap();
+        assert_eq!(
+            sig.matches(&mem, mem.start_addr, end_addr, 0),
+            Some(vec![0x1003, 0x1012])
+        );
+
+        let sig: Signature = "asm(aa**{0,2}dd, raw)".parse().unwrap();
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .unwrap_err()
+                .kind,
+            ResolveErrorKind::NoMatch {
+                start_addr: 0x1000,
+                end_addr
+            }
+        );
+    }
+
+    #[test]
+    fn aligned_match() -> Result<(), Error> {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                0x00, 0x00, 0x00, 0x11, 0x22, 0x33, 0x44, 0x00,
+                0x11, 0x22, 0x33, 0x44, 0x00, 0x00, 0x00, 0x00,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let sig: Signature = "asm(11223344, raw); add(-4)".parse()?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1003));
+
+        let sig: Signature = "asm(11223344, raw, align=8); add(-4)".parse()?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1008));
+        // Alignment is of the address, not the offset into the range.
+        assert_eq!(sig.resolve(&mem, 0x1001, end_addr), Ok(0x1008));
+        assert_eq!(
+            sig.matches(&mem, mem.start_addr, end_addr, 0),
+            Some(vec![0x100c])
+        );
+
+        let sig: Signature = "asm(11223344, raw, align=16)".parse()?;
+        assert!(sig.resolve(&mem, mem.start_addr, end_addr).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fuzzy_match() -> Result<(), Error> {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                // mov rax, [rip+0x8]; test eax, eax
+                0x48, 0x8b, 0x05, 0x08, 0x00, 0x00, 0x00, 0x85, 0xc0,
+                // mov [rip+0x0], rcx; inc eax
+                0x48, 0x89, 0x0d, 0x00, 0x00, 0x00, 0x00, 0xff, 0xc0,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        // The register changed in both instructions.
+        let sig: Signature = "asm(488b0d^^^^^^^^85c9, fuzzy=2)".parse()?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x100f));
+
+        let sig: Signature = "asm(488b0d^^^^^^^^85c9, fuzzy=1)".parse()?;
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .map_err(|e| e.kind),
+            Err(ResolveErrorKind::NoMatch {
+                start_addr: 0x1000,
+                end_addr
+            })
+        );
+
+        let candidates = sig
+            .fuzzy_matches(&mem, mem.start_addr, end_addr, 0, 3)
+            .unwrap();
+        let addrs: Vec<(u64, usize)> = candidates
+            .iter()
+            .map(|m| (m.match_addr, m.mismatches.len()))
+            .collect();
+        assert_eq!(addrs, vec![(0x1000, 2), (0x1009, 3)]);
+        assert_eq!(candidates[0].addr, 0x1003);
+        assert_eq!(
+            candidates[0].to_string(),
+            "0x1000: 2 mismatches (0x1002: expected 0d, found 05; \
+             0x1008: expected c9, found c0)"
+        );
+
+        // A longer skip avoids the mismatch the shortest one has.
+        let sig: Signature = "asm(8b**{0,8}c0)".parse()?;
+        let candidates = sig
+            .fuzzy_matches(&mem, mem.start_addr, end_addr, 0, 1)
+            .unwrap();
+        assert_eq!(candidates[0].match_addr, 0x1001);
+        assert!(candidates[0].mismatches.is_empty());
+
+        let sig: Signature = "ptr(0)".parse()?;
+        assert_eq!(
+            sig.fuzzy_matches(&mem, mem.start_addr, end_addr, 0, 3),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn alternatives() {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                0xff, 0xff, 0xff, 0xff, 0x00, 0x11, 0x22, 0x33,
+                0x04, 0x00, 0x00, 0x00, 0x44, 0x55, 0x66, 0x77,
+                0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let sig = Signature::with_alternatives(&[
+            vec!["asm(00112244^^^^^^^^)".to_string()],
+            vec!["asm(00112233^^^^^^^^)".to_string(), "add(1)".to_string()],
+            vec!["base".to_string()],
+        ])
+        .unwrap();
+        assert_eq!(
+            sig.resolve_alternative(&mem, mem.start_addr, end_addr),
+            Ok((1, 0x1011))
+        );
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1011));
+
+        let sig =
+            Signature::with_alternatives(&[vec!["asm(00112244^^^^^^^^)".to_string()]]).unwrap();
+        assert_eq!(
+            sig.resolve_alternative(&mem, mem.start_addr, end_addr),
+            Err(ResolveError {
+                alternative: 0,
+                op_index: 0,
+                op: "asm(00112244^^^^^^^^)".to_string(),
+                addr: 0x1000,
+                kind: ResolveErrorKind::NoMatch {
+                    start_addr: 0x1000,
+                    end_addr
+                },
+            })
+        );
+
+        assert!(Signature::with_alternatives(&[]).is_err());
+    }
+
+    #[test]
+    fn trace() {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                0xff, 0xff, 0xff, 0xff, 0x00, 0x11, 0x22, 0x33,
+                0x04, 0x00, 0x00, 0x00, 0x44, 0x55, 0x66, 0x77,
+                0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let sig: Signature = "asm(00112233^^^^^^^^); ptr(0x100) | base+0x8; ptr32(0)"
+            .parse()
+            .unwrap();
+        let (result, trace) = sig.resolve_traced(&mem, mem.start_addr, end_addr);
+        assert_eq!(result, Ok((1, 0x4)));
+        let steps: Vec<String> = trace.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            steps,
+            vec![
+                "[0.0] asm(00112233^^^^^^^^) @ 0x1000 -> 0x1010",
+                "[0.1] ptr(256) @ 0x1010 -> can't read pointer at 0x1110",
+                "[1.0] base+0x8 @ 0x1000 -> 0x1008",
+                "[1.1] ptr32(0) @ 0x1008 -> 0x4",
+            ]
+        );
+
+        let e = sig.alternatives[0..1].to_vec();
+        let e = Signature { alternatives: e }
+            .resolve(&mem, mem.start_addr, end_addr)
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "ptr(256) failed at 0x1010 (alternative 0, op 1): can't read pointer at 0x1110"
+        );
+    }
+
+    #[test]
+    fn display_round_trip() -> Result<(), Error> {
+        let sigs = vec![
+            "asm(488b0d^^^^^^^^)",
+            "asm(803d^^^^^^^^01, end=5); ptr(0); ptr32(-8); add(16)",
+            "asm(c705**^^**55, disp=1, end=6)",
+            "asm(e8**{0,8}488d0d^^^^^^^^, end=5)",
+            "asm(488b0d^^^^^^^^85c9, scope=0x40, fuzzy=2)",
+            "asm(0000803f^^^^^^^^, raw, align=8)",
+            "asm(e8^^******cc, raw, scope=0x40); add(-1); follow()",
+            "module(\"game.exe\")+0x1a2b30; ptr(0)",
+            "base-0x10 | base | base+0x8",
+            "xref(\"bad value\") | xref(\"ok\", utf16); asm(4c8d05^^^^^^^^, scope=0x7)",
+        ];
+        for text in sigs {
+            let sig: Signature = text.parse()?;
+            assert_eq!(sig.to_string(), text);
+            assert_eq!(sig.to_string().parse::<Signature>()?, sig);
+        }
+
+        let sig: Signature = "asm(48 8B 0D ^^ ^^ ^^ ^^, end=4);ptr(0x10)".parse()?;
+        assert_eq!(sig.to_string(), "asm(488b0d^^^^^^^^); ptr(16)");
+        assert_eq!(
+            sig,
+            Signature::new(&["asm(488b0d^^^^^^^^)".to_string(), "ptr(16)".to_string()])?
+        );
+
+        assert!("asm(488b0d^^^^^^^^); bogus()".parse::<Signature>().is_err());
+        Ok(())
+    }
+
+    fn parse_error(ops: &[&str]) -> SignatureParseError {
+        let ops: Vec<String> = ops.iter().map(|s| s.to_string()).collect();
+        Signature::new(&ops)
+            .unwrap_err()
+            .downcast::<SignatureParseError>()
+            .unwrap()
+    }
+
+    fn parse_str_error(text: &str) -> SignatureParseError {
+        text.parse::<Signature>()
+            .unwrap_err()
+            .downcast::<SignatureParseError>()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_errors() {
+        let e = parse_error(&["asm(00112233^^^^^^^^*******)"]);
+        assert_eq!(e.message, "expected a pattern of hex bytes, `**` or `^^`");
+        assert_eq!(e.column, 27);
+
+        let e = parse_error(&["asm(0011223)"]);
+        assert_eq!(e.message, "odd hex digit count");
+        assert_eq!(e.column, 11);
+        assert_eq!(
+            e.to_string(),
+            "odd hex digit count at column 11 (alternative 0, op 0)"
+        );
+
+        let e = parse_error(&["asm(001122)", "ptr(0)x"]);
+        assert_eq!(e.message, "unexpected input after op");
+        assert_eq!((e.op_index, e.column), (1, 7));
+
+        let e = parse_error(&["asm(0011, scope=x)"]);
+        assert_eq!(e.message, "expected an integer");
+        assert_eq!(e.column, 17);
+
+        let e = parse_error(&["asm(0011, bogus)"]);
+        assert_eq!(
+            e.message,
+            "expected an asm option: align=, disp=, end=, fuzzy=, raw or scope="
+        );
+        assert_eq!(e.column, 11);
+
+        let e = parse_error(&["asm(0011, disp=3)"]);
+        assert_eq!(e.message, "disp must be 1, 2 or 4");
+        assert_eq!(e.column, 16);
+
+        let e = parse_error(&["asm(00**{6,2}11)"]);
+        assert!(e.message.starts_with("expected a wildcard range"));
+        assert_eq!(e.column, 10);
+
+        let e = parse_error(&["ptr(0"]);
+        assert_eq!((e.message.as_str(), e.column), ("expected `)`", 6));
+
+        let e = parse_error(&["xref(\"abc)"]);
+        assert_eq!((e.message.as_str(), e.column), ("unterminated string", 11));
+
+        #[cfg(not(feature = "x86"))]
+        {
+            let e = parse_error(&["insn(ret)"]);
+            assert_eq!(e.message, "insn() requires memscanner's `x86` feature");
+            let e = parse_error(&["asm(0011, end=auto)"]);
+            assert_eq!(e.message, "end=auto requires memscanner's `x86` feature");
+        }
+
+        let e = parse_error(&["asm(0011, end=x)"]);
+        assert_eq!(
+            (e.message.as_str(), e.column),
+            ("expected an integer or auto", 15)
+        );
+
+        let e = parse_error(&["jump(0)"]);
+        assert!(e.message.starts_with("expected an op"));
+        assert_eq!(e.column, 1);
+
+        let e = parse_str_error("asm(0011); ptr(0) | base; ptr(0x)");
+        assert_eq!(e.message, "expected a 32 bit hex integer");
+        assert_eq!((e.alternative, e.op_index, e.column), (1, 1, 33));
+
+        let e = parse_str_error("base; ptr(0) add(1)");
+        assert_eq!(e.message, "expected `;`, `|` or the end of the signature");
+        assert_eq!((e.op_index, e.column), (1, 14));
+    }
+
+    #[test]
+    fn ida_conversion() -> Result<(), Error> {
+        assert_eq!(
+            pattern_from_ida("48 8B 0D ^^ ^^ ^^ ^^ E8 ? ? ?? ?")?,
+            "488b0d^^^^^^^^e8********"
+        );
+        assert_eq!(
+            pattern_to_ida("488b0d^^^^^^^^e8********")?,
+            "48 8B 0D ^^ ^^ ^^ ^^ E8 ?? ?? ?? ??"
+        );
+        assert_eq!(pattern_from_ida("E8 ?{2,6} C3")?, "e8**{2,6}c3");
+        assert_eq!(pattern_to_ida("e8**{2,6}c3")?, "E8 ??{2,6} C3");
+        assert!(pattern_from_ida("48 8B 0").is_err());
+        assert!(pattern_to_ida("488b0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn single_lea_ida() {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                0xff, 0xff, 0xff, 0xff, 0x00, 0x11, 0x22, 0x33,
+                0x04, 0x00, 0x00, 0x00, 0x44, 0x55, 0x66, 0x77,
+                0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+
+        let sig = Signature::new(&["asm(00 11 22 33 ^ ^ ^ ^ ? ? ? ?)".to_string()]).unwrap();
+        let offset = sig
+            .resolve(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)
+            .unwrap();
+        assert_eq!(offset, 0x1010);
+    }
 }
@@ -1,17 +1,28 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take},
-    character::complete::digit1,
-    combinator::{map_res, opt, recognize, value},
-    multi::many1,
-    sequence::pair,
+    bytes::complete::{is_not, tag, take, take_till},
+    character::complete::{digit1, hex_digit1, one_of, space0, space1},
+    combinator::{cut, map, map_res, not, opt, peek, recognize, value, verify},
+    error::{context, VerboseError},
+    multi::{many0, many1, separated_nonempty_list},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
     IResult,
 };
 
-use super::{Match, Op};
+use std::convert::TryFrom;
+
+#[cfg(feature = "x86")]
+use super::x86::{InsnOp, InsnTemplate, OperandTemplate, Slot};
+use super::{AsmOp, Decode, Encoding, Match, Op};
+#[cfg(feature = "x86")]
+use nom::bytes::complete::take_while1;
+
+// Every parser keeps the full error trail so that `Signature` can report
+// where parsing failed and what was expected there.
+pub(super) type ParseResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
 
 #[rustfmt::skip]
-fn match_signed_integer(input: &str) -> IResult<&str, &str> {
+fn match_signed_integer(input: &str) -> ParseResult<'_, &str> {
     recognize(pair(
         opt(
             alt((
@@ -23,8 +34,40 @@ fn match_signed_integer(input: &str) -> IResult<&str, &str> {
     ))(input)
 }
 
-fn parse_i32(input: &str) -> IResult<&str, i32> {
-    map_res(match_signed_integer, |s: &str| s.parse::<i32>())(input)
+fn parse_hex_i32(input: &str) -> ParseResult<'_, i32> {
+    let (input, sign) = opt(alt((tag("-"), tag("+"))))(input)?;
+    let negative = sign == Some("-");
+    let (input, _) = tag("0x")(input)?;
+    cut(context(
+        "expected a 32 bit hex integer",
+        map_res(hex_digit1, move |s: &str| {
+            let val = i64::from_str_radix(s, 16)?;
+            let val = if negative { -val } else { val };
+            Ok::<i32, failure::Error>(i32::try_from(val)?)
+        }),
+    ))(input)
+}
+
+// Parses a signed integer in either decimal or `0x` prefixed hex.
+fn parse_i32(input: &str) -> ParseResult<'_, i32> {
+    alt((
+        parse_hex_i32,
+        map_res(match_signed_integer, |s: &str| s.parse::<i32>()),
+    ))(input)
+}
+
+// Parses an unsigned integer in either decimal or `0x` prefixed hex.
+fn parse_u32(input: &str) -> ParseResult<'_, u32> {
+    alt((
+        preceded(
+            tag("0x"),
+            cut(context(
+                "expected a 32 bit hex integer",
+                map_res(hex_digit1, |s: &str| u32::from_str_radix(s, 16)),
+            )),
+        ),
+        map_res(digit1, |s: &str| s.parse::<u32>()),
+    ))(input)
 }
 
 fn literal_from_hex(input: &str) -> Result<Match, std::num::ParseIntError> {
@@ -32,40 +75,508 @@ fn literal_from_hex(input: &str) -> Result<Match, std::num::ParseIntError> {
     Ok(Match::Literal(val))
 }
 
-fn parse_literal(input: &str) -> IResult<&str, Match> {
+fn parse_literal(input: &str) -> ParseResult<'_, Match> {
     map_res(take(2usize), literal_from_hex)(input)
 }
 
-fn parse_any(input: &str) -> IResult<&str, Match> {
-    value(Match::Any, tag("**"))(input)
+// The longest gap a skip may cover.
+const MAX_SKIP: u32 = 0x100;
+
+// Parses the `{min,max}` that turns a wildcard into a skip.
+fn parse_skip_range(input: &str) -> ParseResult<'_, (u32, u32)> {
+    preceded(
+        tag("{"),
+        cut(context(
+            "expected a wildcard range `{min,max}` with min <= max <= 256",
+            terminated(
+                verify(
+                    separated_pair(parse_u32, tag(","), parse_u32),
+                    |(min, max)| min <= max && *max <= MAX_SKIP,
+                ),
+                tag("}"),
+            ),
+        )),
+    )(input)
+}
+
+// Follows a wildcard with an optional `{min,max}` range.
+fn with_skip_range<'a>(
+    wildcard: impl Fn(&'a str) -> ParseResult<'a, &'a str>,
+) -> impl Fn(&'a str) -> ParseResult<'a, Match> {
+    map(
+        preceded(wildcard, opt(parse_skip_range)),
+        |range| match range {
+            Some((min, max)) => Match::Skip { min, max },
+            None => Match::Any,
+        },
+    )
 }
 
-fn parse_position(input: &str) -> IResult<&str, Match> {
+fn parse_any(input: &str) -> ParseResult<'_, Match> {
+    with_skip_range(tag("**"))(input)
+}
+
+fn parse_position(input: &str) -> ParseResult<'_, Match> {
     value(Match::Position, tag("^^"))(input)
 }
 
-fn parse_match(input: &str) -> IResult<&str, Match> {
+fn parse_match(input: &str) -> ParseResult<'_, Match> {
     alt((parse_any, parse_position, parse_literal))(input)
 }
 
-fn parse_lea(input: &str) -> IResult<&str, Op> {
+// IDA and x64dbg write wildcards as `?` or `??`.  Neither tool has a notion
+// of a position marker so we borrow `^`/`^^` from our own syntax.
+fn parse_ida_any(input: &str) -> ParseResult<'_, Match> {
+    with_skip_range(alt((tag("??"), tag("?"))))(input)
+}
+
+fn parse_ida_position(input: &str) -> ParseResult<'_, Match> {
+    value(Match::Position, alt((tag("^^"), tag("^"))))(input)
+}
+
+fn parse_ida_match(input: &str) -> ParseResult<'_, Match> {
+    alt((parse_ida_any, parse_ida_position, parse_literal))(input)
+}
+
+/// Parses a space separated IDA/x64dbg style pattern (`48 8B 0D ?? ?? E8 ?`).
+pub(super) fn parse_ida_pattern(input: &str) -> ParseResult<'_, Vec<Match>> {
+    delimited(
+        space0,
+        separated_nonempty_list(space1, parse_ida_match),
+        space0,
+    )(input)
+}
+
+/// Parses a compact pattern (`488B0D****E8**`).
+pub(super) fn parse_pattern(input: &str) -> ParseResult<'_, Vec<Match>> {
+    terminated(
+        many1(parse_match),
+        // A lone hex digit can only be a typo.
+        cut(context(
+            "odd hex digit count",
+            not(one_of("0123456789abcdefABCDEF")),
+        )),
+    )(input)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum AsmOption {
+    Disp(u8),
+    End(u32),
+    // `end=auto`
+    Decoded,
+    Raw,
+    Scope(u32),
+    Fuzzy(u32),
+    Align(u32),
+}
+
+fn parse_asm_option(input: &str) -> ParseResult<'_, AsmOption> {
+    alt((
+        map(
+            preceded(
+                tag("disp="),
+                cut(context(
+                    "disp must be 1, 2 or 4",
+                    verify(parse_u32, |v| *v == 1 || *v == 2 || *v == 4),
+                )),
+            ),
+            |v| AsmOption::Disp(v as u8),
+        ),
+        preceded(
+            tag("end="),
+            cut(alt((
+                parse_end_auto,
+                map(
+                    context("expected an integer or auto", parse_u32),
+                    AsmOption::End,
+                ),
+            ))),
+        ),
+        value(AsmOption::Raw, tag("raw")),
+        map(
+            preceded(
+                tag("scope="),
+                cut(context("expected an integer", parse_u32)),
+            ),
+            AsmOption::Scope,
+        ),
+        map(
+            preceded(
+                tag("fuzzy="),
+                cut(context("expected an integer", parse_u32)),
+            ),
+            AsmOption::Fuzzy,
+        ),
+        map(
+            preceded(
+                tag("align="),
+                cut(context(
+                    "align must be a power of two",
+                    verify(parse_u32, |v| v.is_power_of_two()),
+                )),
+            ),
+            AsmOption::Align,
+        ),
+    ))(input)
+}
+
+#[cfg(feature = "x86")]
+fn parse_end_auto(input: &str) -> ParseResult<'_, AsmOption> {
+    value(AsmOption::Decoded, tag("auto"))(input)
+}
+
+#[cfg(not(feature = "x86"))]
+fn parse_end_auto(input: &str) -> ParseResult<'_, AsmOption> {
+    let (input, _) = tag("auto")(input)?;
+    cut(context(
+        "end=auto requires memscanner's `x86` feature",
+        map(not(take(0usize)), |_| AsmOption::Decoded),
+    ))(input)
+}
+
+fn parse_arg_separator(input: &str) -> ParseResult<'_, &str> {
+    delimited(space0, tag(","), space0)(input)
+}
+
+fn parse_close(input: &str) -> ParseResult<'_, &str> {
+    cut(context("expected `)`", preceded(space0, tag(")"))))(input)
+}
+
+// Patterns containing spaces are IDA style, everything else is compact.
+// Deciding up front keeps errors pointing into the style actually used.
+fn parse_asm_pattern(input: &str) -> ParseResult<'_, Vec<Match>> {
+    let (_, body) = peek(take_till(|c| c == ',' || c == ')'))(input)?;
+    // Only IDA patterns contain `?`, but a skip range's `,` can cut the body
+    // short before any whitespace.
+    let is_ida = body.trim().contains(char::is_whitespace) || body.contains('?');
+    let pattern_parser = match is_ida {
+        true => parse_ida_pattern,
+        false => parse_pattern,
+    };
+    cut(context(
+        "expected a pattern of hex bytes, `**` or `^^`",
+        terminated(pattern_parser, peek(preceded(space0, one_of(",)")))),
+    ))(input)
+}
+
+fn parse_lea(input: &str) -> ParseResult<'_, Op> {
     let (input, _) = tag("asm(")(input)?;
-    let (input, pattern) = many1(parse_match)(input)?;
-    let (input, _) = tag(")")(input)?;
+    let (input, pattern) = parse_asm_pattern(input)?;
+    let (input, options) = many0(preceded(
+        parse_arg_separator,
+        cut(context(
+            "expected an asm option: align=, disp=, end=, fuzzy=, raw or scope=",
+            parse_asm_option,
+        )),
+    ))(input)?;
+    let (input, _) = parse_close(input)?;
 
-    Ok((input, Op::Asm(pattern)))
+    let mut disp_size = 4;
+    let mut insn_end = None;
+    let mut raw = false;
+    let mut decoded = false;
+    let mut scope = None;
+    let mut fuzzy = 0;
+    let mut align = 1;
+    for option in options {
+        match option {
+            AsmOption::Disp(d) => disp_size = d,
+            AsmOption::End(e) => insn_end = Some(e),
+            AsmOption::Decoded => decoded = true,
+            AsmOption::Raw => raw = true,
+            AsmOption::Scope(s) => scope = Some(s),
+            AsmOption::Fuzzy(n) => fuzzy = n,
+            AsmOption::Align(a) => align = a,
+        }
+    }
+
+    let mut op = AsmOp::new(pattern);
+    op.decode = match (raw, decoded) {
+        (true, _) => Decode::Raw,
+        #[cfg(feature = "x86")]
+        (false, true) => Decode::Operand,
+        // The instruction ends right after the displacement unless told otherwise.
+        _ => Decode::Rip {
+            disp_size,
+            insn_end: insn_end.unwrap_or(disp_size as u32),
+        },
+    };
+    op.scope = scope;
+    op.fuzzy = fuzzy;
+    op.align = align;
+
+    Ok((input, Op::Asm(op)))
+}
+
+// Parses the `<offset>)` tail shared by the single offset ops.
+fn parse_offset_arg(input: &str) -> ParseResult<'_, i32> {
+    let (input, offset) = cut(context("expected an integer offset", parse_i32))(input)?;
+    let (input, _) = parse_close(input)?;
+
+    Ok((input, offset))
 }
 
-fn parse_ptr(input: &str) -> IResult<&str, Op> {
+fn parse_ptr(input: &str) -> ParseResult<'_, Op> {
     let (input, _) = tag("ptr(")(input)?;
-    let (input, offset) = parse_i32(input)?;
-    let (input, _) = tag(")")(input)?;
+    let (input, offset) = parse_offset_arg(input)?;
 
     Ok((input, Op::Ptr(offset)))
 }
 
-pub(super) fn parse_op(input: &str) -> IResult<&str, Op> {
-    alt((parse_lea, parse_ptr))(input)
+fn parse_ptr32(input: &str) -> ParseResult<'_, Op> {
+    let (input, _) = tag("ptr32(")(input)?;
+    let (input, offset) = parse_offset_arg(input)?;
+
+    Ok((input, Op::Ptr32(offset)))
+}
+
+fn parse_add(input: &str) -> ParseResult<'_, Op> {
+    let (input, _) = tag("add(")(input)?;
+    let (input, offset) = parse_offset_arg(input)?;
+
+    Ok((input, Op::Add(offset)))
+}
+
+// Parses a double quoted string.  Escapes are not supported.
+fn parse_string(input: &str) -> ParseResult<'_, String> {
+    map(
+        preceded(
+            tag("\""),
+            cut(terminated(
+                opt(is_not("\"")),
+                context("unterminated string", tag("\"")),
+            )),
+        ),
+        |s: Option<&str>| s.unwrap_or("").to_string(),
+    )(input)
+}
+
+// Parses an optional `+0x10`/`-16` style offset following a base.
+fn parse_base_offset(input: &str) -> ParseResult<'_, i64> {
+    map(
+        opt(pair(
+            delimited(space0, alt((tag("+"), tag("-"))), space0),
+            cut(context("expected an integer offset", parse_u32)),
+        )),
+        |o| match o {
+            Some(("-", v)) => -(v as i64),
+            Some((_, v)) => v as i64,
+            None => 0,
+        },
+    )(input)
+}
+
+fn parse_module(input: &str) -> ParseResult<'_, Op> {
+    let (input, name) = alt((
+        map(
+            preceded(
+                tag("module("),
+                cut(terminated(
+                    context("expected a quoted module name", parse_string),
+                    parse_close,
+                )),
+            ),
+            Some,
+        ),
+        value(None, tag("base")),
+    ))(input)?;
+    let (input, offset) = parse_base_offset(input)?;
+
+    Ok((input, Op::Module(name, offset)))
+}
+
+fn parse_encoding(input: &str) -> ParseResult<'_, Encoding> {
+    alt((
+        value(Encoding::Ascii, tag("ascii")),
+        value(Encoding::Utf16, tag("utf16")),
+    ))(input)
+}
+
+fn parse_xref(input: &str) -> ParseResult<'_, Op> {
+    let (input, _) = tag("xref(")(input)?;
+    let (input, text) = cut(context("expected a quoted string", parse_string))(input)?;
+    let (input, encoding) = opt(preceded(
+        parse_arg_separator,
+        cut(context("expected `ascii` or `utf16`", parse_encoding)),
+    ))(input)?;
+    let (input, _) = parse_close(input)?;
+
+    Ok((input, Op::Xref(text, encoding.unwrap_or(Encoding::Ascii))))
+}
+
+fn parse_follow(input: &str) -> ParseResult<'_, Op> {
+    let (input, _) = tag("follow")(input)?;
+    let (input, _) = cut(context("expected `()`", tag("()")))(input)?;
+
+    Ok((input, Op::Follow))
+}
+
+// Parses a signed decimal or `0x` prefixed hex integer in an instruction
+// template.
+#[cfg(feature = "x86")]
+fn parse_template_int(input: &str) -> ParseResult<'_, i64> {
+    let (input, negative) = map(opt(tag("-")), |s| s.is_some())(input)?;
+    let (input, val) = alt((
+        preceded(
+            tag("0x"),
+            cut(context(
+                "expected a 64 bit hex integer",
+                map_res(hex_digit1, |s: &str| u64::from_str_radix(s, 16)),
+            )),
+        ),
+        map_res(digit1, |s: &str| s.parse::<u64>()),
+    ))(input)?;
+    let val = val as i64;
+
+    Ok((input, if negative { val.wrapping_neg() } else { val }))
+}
+
+// Mnemonics and registers are matched case insensitively.
+#[cfg(feature = "x86")]
+fn parse_template_name(input: &str) -> ParseResult<'_, String> {
+    map(
+        verify(
+            take_while1(|c: char| c.is_ascii_alphanumeric()),
+            |s: &str| s.starts_with(|c: char| c.is_ascii_alphabetic()),
+        ),
+        |s: &str| s.to_lowercase(),
+    )(input)
+}
+
+#[cfg(feature = "x86")]
+fn parse_slot(input: &str) -> ParseResult<'_, Slot> {
+    alt((
+        value(Slot::Any, tag("?")),
+        value(Slot::Capture, tag("^")),
+        map(parse_template_int, Slot::Value),
+    ))(input)
+}
+
+// Parses `[?]`, `[^]`, `[rax]` or `[rip+?]` style memory operands.
+#[cfg(feature = "x86")]
+fn parse_template_memory(input: &str) -> ParseResult<'_, OperandTemplate> {
+    let (input, _) = terminated(tag("["), space0)(input)?;
+    let (input, operand) = cut(context(
+        "expected `?`, `^` or a base register followed by a displacement",
+        alt((
+            map(parse_slot, |disp| OperandTemplate::Memory {
+                base: None,
+                disp,
+            }),
+            map(
+                pair(
+                    parse_template_name,
+                    opt(pair(
+                        delimited(space0, alt((tag("+"), tag("-"))), space0),
+                        cut(context("expected a displacement", parse_slot)),
+                    )),
+                ),
+                |(base, disp)| {
+                    let disp = match disp {
+                        Some(("-", Slot::Value(v))) => Slot::Value(v.wrapping_neg()),
+                        Some((_, disp)) => disp,
+                        None => Slot::Value(0),
+                    };
+                    OperandTemplate::Memory {
+                        base: Some(base),
+                        disp,
+                    }
+                },
+            ),
+        )),
+    ))(input)?;
+    let (input, _) = preceded(space0, cut(context("expected `]`", tag("]"))))(input)?;
+
+    Ok((input, operand))
+}
+
+#[cfg(feature = "x86")]
+fn parse_operand_template(input: &str) -> ParseResult<'_, OperandTemplate> {
+    context(
+        "expected an operand: a register, an integer, `?`, `^` or `[...]`",
+        alt((
+            value(OperandTemplate::Any, tag("?")),
+            value(OperandTemplate::Capture, tag("^")),
+            parse_template_memory,
+            map(parse_template_int, OperandTemplate::Immediate),
+            map(parse_template_name, OperandTemplate::Register),
+        )),
+    )(input)
+}
+
+// Parses `?` or a mnemonic followed by comma separated operands.
+#[cfg(feature = "x86")]
+fn parse_insn_template(input: &str) -> ParseResult<'_, InsnTemplate> {
+    context(
+        "expected an instruction template",
+        alt((
+            value(
+                InsnTemplate {
+                    mnemonic: None,
+                    operands: vec![],
+                },
+                tag("?"),
+            ),
+            map(
+                pair(
+                    parse_template_name,
+                    opt(preceded(
+                        space1,
+                        separated_nonempty_list(
+                            delimited(space0, tag(","), space0),
+                            cut(parse_operand_template),
+                        ),
+                    )),
+                ),
+                |(mnemonic, operands)| InsnTemplate {
+                    mnemonic: Some(mnemonic),
+                    operands: operands.unwrap_or_default(),
+                },
+            ),
+        )),
+    )(input)
+}
+
+#[cfg(feature = "x86")]
+fn parse_insn(input: &str) -> ParseResult<'_, Op> {
+    let (input, _) = tag("insn(")(input)?;
+    let (input, templates) = delimited(
+        space0,
+        separated_nonempty_list(
+            delimited(space0, tag(";"), space0),
+            cut(parse_insn_template),
+        ),
+        space0,
+    )(input)?;
+    let (input, _) = parse_close(input)?;
+
+    Ok((input, Op::Insn(InsnOp { templates })))
+}
+
+#[cfg(not(feature = "x86"))]
+fn parse_insn(input: &str) -> ParseResult<'_, Op> {
+    let (input, _) = tag("insn(")(input)?;
+    cut(context(
+        "insn() requires memscanner's `x86` feature",
+        map(not(take(0usize)), |_| Op::Follow),
+    ))(input)
+}
+
+pub(super) fn parse_op(input: &str) -> ParseResult<'_, Op> {
+    context(
+        "expected an op: asm(), insn(), ptr(), ptr32(), add(), follow(), module(), base or xref()",
+        alt((
+            parse_lea,
+            parse_ptr,
+            parse_ptr32,
+            parse_add,
+            parse_follow,
+            parse_module,
+            parse_xref,
+            parse_insn,
+        )),
+    )(input)
 }
 
 #[cfg(test)]
@@ -73,6 +584,20 @@ mod tests {
     use super::*;
 
     use failure::Error;
+    use nom::error::{ErrorKind, VerboseErrorKind};
+
+    // Returns the location and kind of the first nom error recorded.
+    fn innermost_error<T>(result: ParseResult<'_, T>) -> Option<(&str, ErrorKind)> {
+        match result {
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                e.errors.iter().find_map(|(i, kind)| match kind {
+                    VerboseErrorKind::Nom(k) => Some((*i, *k)),
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    }
 
     #[test]
     fn match_from_hext_test() -> Result<(), Error> {
@@ -95,8 +620,8 @@ mod tests {
     fn parse_any_test() -> Result<(), Error> {
         assert_eq!(parse_any("**"), Ok(("", Match::Any)));
         assert_eq!(
-            parse_any("ab"),
-            Err(nom::Err::Error(("ab", nom::error::ErrorKind::Tag)))
+            innermost_error(parse_any("ab")),
+            Some(("ab", nom::error::ErrorKind::Tag))
         );
 
         Ok(())
@@ -106,8 +631,8 @@ mod tests {
     fn parse_position_test() -> Result<(), Error> {
         assert_eq!(parse_position("^^"), Ok(("", Match::Position)));
         assert_eq!(
-            parse_position("**"),
-            Err(nom::Err::Error(("**", nom::error::ErrorKind::Tag)))
+            innermost_error(parse_position("**")),
+            Some(("**", nom::error::ErrorKind::Tag))
         );
 
         Ok(())
@@ -118,6 +643,145 @@ mod tests {
         assert_eq!(parse_match("ab"), Ok(("", Match::Literal(0xab))));
         assert_eq!(parse_match("**"), Ok(("", Match::Any)));
         assert_eq!(parse_match("^^"), Ok(("", Match::Position)));
+        assert_eq!(
+            parse_match("**{2,6}"),
+            Ok(("", Match::Skip { min: 2, max: 6 }))
+        );
+        assert_eq!(
+            parse_match("**{0,0x10}"),
+            Ok(("", Match::Skip { min: 0, max: 0x10 }))
+        );
+        assert!(parse_match("**{2,}").is_err());
+        assert!(parse_match("**{2,0x101}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_ida_match_test() -> Result<(), Error> {
+        assert_eq!(parse_ida_match("8B"), Ok(("", Match::Literal(0x8b))));
+        assert_eq!(parse_ida_match("?"), Ok(("", Match::Any)));
+        assert_eq!(parse_ida_match("??"), Ok(("", Match::Any)));
+        assert_eq!(parse_ida_match("^"), Ok(("", Match::Position)));
+        assert_eq!(parse_ida_match("^^"), Ok(("", Match::Position)));
+        assert_eq!(
+            parse_ida_match("?{1,3}"),
+            Ok(("", Match::Skip { min: 1, max: 3 }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_ida_pattern_test() -> Result<(), Error> {
+        assert_eq!(
+            parse_op("asm(48 8B 0D ^^ ^ ^^ ^ E8 ? ?? ? ?)"),
+            Ok((
+                "",
+                Op::Asm(AsmOp::new(vec![
+                    Match::Literal(0x48),
+                    Match::Literal(0x8b),
+                    Match::Literal(0x0d),
+                    Match::Position,
+                    Match::Position,
+                    Match::Position,
+                    Match::Position,
+                    Match::Literal(0xe8),
+                    Match::Any,
+                    Match::Any,
+                    Match::Any,
+                    Match::Any,
+                ]))
+            ))
+        );
+        assert_eq!(
+            parse_ida_pattern("48 8B0D"),
+            Ok(("0D", vec![Match::Literal(0x48), Match::Literal(0x8b)]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_u32_test() -> Result<(), Error> {
+        assert_eq!(parse_u32("12"), Ok(("", 12)));
+        assert_eq!(parse_u32("0x1f"), Ok(("", 0x1f)));
+        assert_eq!(parse_u32("0xffffffff"), Ok(("", 0xffffffff)));
+        assert!(parse_u32("0x100000000").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_asm_options_test() -> Result<(), Error> {
+        let pattern = vec![Match::Literal(0x80), Match::Position];
+        assert_eq!(
+            parse_op("asm(80^^, end=5)"),
+            Ok((
+                "",
+                Op::Asm(AsmOp {
+                    decode: Decode::Rip {
+                        disp_size: 4,
+                        insn_end: 5
+                    },
+                    ..AsmOp::new(pattern.clone())
+                })
+            ))
+        );
+        assert_eq!(
+            parse_op("asm(80 ^^ , disp=1)"),
+            Ok((
+                "",
+                Op::Asm(AsmOp {
+                    decode: Decode::Rip {
+                        disp_size: 1,
+                        insn_end: 1
+                    },
+                    ..AsmOp::new(pattern.clone())
+                })
+            ))
+        );
+        assert_eq!(
+            parse_op("asm(80^^,raw)"),
+            Ok((
+                "",
+                Op::Asm(AsmOp {
+                    decode: Decode::Raw,
+                    ..AsmOp::new(pattern.clone())
+                })
+            ))
+        );
+        assert_eq!(
+            parse_op("asm(80^^, raw, scope=0x100)"),
+            Ok((
+                "",
+                Op::Asm(AsmOp {
+                    decode: Decode::Raw,
+                    scope: Some(0x100),
+                    ..AsmOp::new(pattern.clone())
+                })
+            ))
+        );
+        assert_eq!(
+            parse_op("asm(80^^, fuzzy=2)"),
+            Ok((
+                "",
+                Op::Asm(AsmOp {
+                    fuzzy: 2,
+                    ..AsmOp::new(pattern.clone())
+                })
+            ))
+        );
+        assert_eq!(
+            parse_op("asm(80^^, raw, align=8)"),
+            Ok((
+                "",
+                Op::Asm(AsmOp {
+                    decode: Decode::Raw,
+                    align: 8,
+                    ..AsmOp::new(pattern.clone())
+                })
+            ))
+        );
+        assert!(parse_op("asm(80^^, align=0)").is_err());
+        assert!(parse_op("asm(80^^, align=6)").is_err());
+        assert!(parse_op("asm(80^^, disp=3)").is_err());
         Ok(())
     }
 
@@ -129,11 +793,16 @@ mod tests {
         }
 
         assert_eq!(
-            parse_i32("a1"),
-            Err(nom::Err::Error(("a1", nom::error::ErrorKind::Digit)))
+            innermost_error(parse_i32("a1")),
+            Some(("a1", nom::error::ErrorKind::Digit))
         );
         assert_eq!(parse_i32("1a"), Ok(("a", 1)));
 
+        assert_eq!(parse_i32("0x10"), Ok(("", 0x10)));
+        assert_eq!(parse_i32("-0x10"), Ok(("", -0x10)));
+        assert_eq!(parse_i32("-0x80000000"), Ok(("", -0x8000_0000)));
+        assert!(parse_i32("0x80000000").is_err());
+
         Ok(())
     }
 
@@ -141,6 +810,43 @@ mod tests {
     fn parse_ptr_test() -> Result<(), Error> {
         assert_eq!(parse_ptr("ptr(-1)"), Ok(("", Op::Ptr(-1))));
         assert_eq!(parse_ptr("ptr(8)"), Ok(("", Op::Ptr(8))));
+        assert_eq!(parse_ptr("ptr(0x18)"), Ok(("", Op::Ptr(0x18))));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_new_ops_test() -> Result<(), Error> {
+        assert_eq!(parse_op("ptr32(4)"), Ok(("", Op::Ptr32(4))));
+        assert_eq!(parse_op("add(-0x20)"), Ok(("", Op::Add(-0x20))));
+        assert_eq!(parse_op("follow()"), Ok(("", Op::Follow)));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_module_test() -> Result<(), Error> {
+        assert_eq!(
+            parse_op("module(\"game.exe\")+0x1A2B30"),
+            Ok(("", Op::Module(Some("game.exe".to_string()), 0x1a2b30)))
+        );
+        assert_eq!(
+            parse_op("module(\"game.exe\")"),
+            Ok(("", Op::Module(Some("game.exe".to_string()), 0)))
+        );
+        assert_eq!(parse_op("base - 0x10"), Ok(("", Op::Module(None, -0x10))));
+        assert_eq!(parse_op("base+16"), Ok(("", Op::Module(None, 16))));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_xref_test() -> Result<(), Error> {
+        assert_eq!(
+            parse_op("xref(\"Can't open %s\")"),
+            Ok(("", Op::Xref("Can't open %s".to_string(), Encoding::Ascii)))
+        );
+        assert_eq!(
+            parse_op("xref(\"(x)\", utf16)"),
+            Ok(("", Op::Xref("(x)".to_string(), Encoding::Utf16)))
+        );
         Ok(())
     }
 
@@ -150,7 +856,7 @@ mod tests {
             parse_op("asm(01234567********^^^^^^^^89abcdef)"),
             Ok((
                 "",
-                Op::Asm(vec![
+                Op::Asm(AsmOp::new(vec![
                     Match::Literal(0x01),
                     Match::Literal(0x23),
                     Match::Literal(0x45),
@@ -167,7 +873,7 @@ mod tests {
                     Match::Literal(0xab),
                     Match::Literal(0xcd),
                     Match::Literal(0xef),
-                ])
+                ]))
             ))
         );
         Ok(())
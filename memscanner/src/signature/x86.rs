@@ -0,0 +1,422 @@
+use super::{ResolveErrorKind, MAX_INSN_LEN};
+use crate::MemReader;
+use iced_x86::{Decoder, DecoderOptions, Instruction, OpKind, Register};
+use std::fmt;
+
+// A displacement or immediate in an instruction template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Slot {
+    // `?` matches anything.
+    Any,
+    // `^` matches anything and makes its address the op's result.
+    Capture,
+    Value(i64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum OperandTemplate {
+    // `?` matches any operand.
+    Any,
+    // `^` matches a memory, branch or immediate operand and makes the
+    // address or value it refers to the op's result.
+    Capture,
+    Register(String),
+    Immediate(i64),
+    // `[base+disp]`.  A `None` base matches any memory operand.
+    Memory { base: Option<String>, disp: Slot },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct InsnTemplate {
+    // `None` matches any instruction.
+    pub mnemonic: Option<String>,
+    pub operands: Vec<OperandTemplate>,
+}
+
+// A sequence of consecutive instructions to search for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct InsnOp {
+    pub templates: Vec<InsnTemplate>,
+}
+
+fn fmt_int(f: &mut fmt::Formatter, val: i64) -> fmt::Result {
+    if val < 0 {
+        write!(f, "-0x{:x}", -(val as i128))
+    } else {
+        write!(f, "0x{:x}", val)
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Slot::Any => write!(f, "?"),
+            Slot::Capture => write!(f, "^"),
+            Slot::Value(v) => fmt_int(f, *v),
+        }
+    }
+}
+
+impl fmt::Display for OperandTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OperandTemplate::Any => write!(f, "?"),
+            OperandTemplate::Capture => write!(f, "^"),
+            OperandTemplate::Register(name) => write!(f, "{}", name),
+            OperandTemplate::Immediate(v) => fmt_int(f, *v),
+            OperandTemplate::Memory { base: None, disp } => write!(f, "[{}]", disp),
+            OperandTemplate::Memory {
+                base: Some(base),
+                disp: Slot::Value(0),
+            } => write!(f, "[{}]", base),
+            OperandTemplate::Memory {
+                base: Some(base),
+                disp: Slot::Value(v),
+            } if *v < 0 => write!(f, "[{}-0x{:x}]", base, -(*v as i128)),
+            OperandTemplate::Memory {
+                base: Some(base),
+                disp,
+            } => write!(f, "[{}+{}]", base, disp),
+        }
+    }
+}
+
+impl fmt::Display for InsnTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = self.mnemonic.as_deref().unwrap_or("?");
+        let operands: Vec<String> = self.operands.iter().map(|o| o.to_string()).collect();
+        match operands.is_empty() {
+            true => write!(f, "{}", mnemonic),
+            false => write!(f, "{} {}", mnemonic, operands.join(", ")),
+        }
+    }
+}
+
+impl fmt::Display for InsnOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let templates: Vec<String> = self.templates.iter().map(|t| t.to_string()).collect();
+        write!(f, "insn({})", templates.join("; "))
+    }
+}
+
+fn register_name(reg: Register) -> String {
+    format!("{:?}", reg).to_lowercase()
+}
+
+fn is_immediate(kind: OpKind) -> bool {
+    matches!(
+        kind,
+        OpKind::Immediate8
+            | OpKind::Immediate8_2nd
+            | OpKind::Immediate16
+            | OpKind::Immediate32
+            | OpKind::Immediate64
+            | OpKind::Immediate8to16
+            | OpKind::Immediate8to32
+            | OpKind::Immediate8to64
+            | OpKind::Immediate32to64
+    )
+}
+
+fn is_branch(kind: OpKind) -> bool {
+    matches!(
+        kind,
+        OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+    )
+}
+
+// The displacement as written, which for RIP-relative operands is relative
+// to the end of the instruction.
+fn displacement(insn: &Instruction) -> i64 {
+    match insn.is_ip_rel_memory_operand() {
+        true => insn.ip_rel_memory_address().wrapping_sub(insn.next_ip()) as i64,
+        false => insn.memory_displacement64() as i64,
+    }
+}
+
+// The address a memory operand refers to, if it can be known without
+// register values.
+fn memory_address(insn: &Instruction) -> u64 {
+    match insn.is_ip_rel_memory_operand() {
+        true => insn.ip_rel_memory_address(),
+        false => insn.memory_displacement64(),
+    }
+}
+
+// The address or value operand `i` refers to.
+fn operand_value(insn: &Instruction, i: u32) -> Option<u64> {
+    let kind = insn.op_kind(i);
+    if kind == OpKind::Memory {
+        Some(memory_address(insn))
+    } else if is_branch(kind) {
+        Some(insn.near_branch_target())
+    } else if is_immediate(kind) {
+        Some(insn.immediate(i))
+    } else {
+        None
+    }
+}
+
+// Returns `None` if `template` doesn't match operand `i` of `insn`, or the
+// captured value if it does.
+fn match_operand(template: &OperandTemplate, insn: &Instruction, i: u32) -> Option<Option<u64>> {
+    let kind = insn.op_kind(i);
+    let matched = match template {
+        OperandTemplate::Any => true,
+        OperandTemplate::Capture => return operand_value(insn, i).map(Some),
+        OperandTemplate::Register(name) => {
+            kind == OpKind::Register && register_name(insn.op_register(i)) == *name
+        }
+        OperandTemplate::Immediate(v) => is_immediate(kind) && insn.immediate(i) == *v as u64,
+        OperandTemplate::Memory { base, disp } => {
+            if kind != OpKind::Memory {
+                return None;
+            }
+            if let Some(base) = base {
+                if register_name(insn.memory_base()) != *base {
+                    return None;
+                }
+            }
+            match disp {
+                Slot::Any => true,
+                Slot::Capture => return Some(Some(memory_address(insn))),
+                Slot::Value(v) => displacement(insn) == *v,
+            }
+        }
+    };
+    match matched {
+        true => Some(None),
+        false => None,
+    }
+}
+
+// Returns `None` if `template` doesn't match `insn`, or the first captured
+// value if it does.
+fn match_insn(template: &InsnTemplate, insn: &Instruction) -> Option<Option<u64>> {
+    if insn.is_invalid() {
+        return None;
+    }
+    let mnemonic = match &template.mnemonic {
+        Some(m) => m,
+        None => return Some(None),
+    };
+    if format!("{:?}", insn.mnemonic()).to_lowercase() != *mnemonic
+        || insn.op_count() as usize != template.operands.len()
+    {
+        return None;
+    }
+    let mut capture = None;
+    for (i, operand) in template.operands.iter().enumerate() {
+        let value = match_operand(operand, insn, i as u32)?;
+        capture = capture.or(value);
+    }
+    Some(capture)
+}
+
+// Decode instructions from the start of `bytes` and match them against
+// `templates`.  Returns the first captured value, or `ip` if there is none.
+fn match_templates(bytes: &[u8], ip: u64, templates: &[InsnTemplate]) -> Option<u64> {
+    let mut decoder = Decoder::with_ip(64, bytes, ip, DecoderOptions::NONE);
+    let mut capture = None;
+    for template in templates {
+        if !decoder.can_decode() {
+            return None;
+        }
+        let value = match_insn(template, &decoder.decode())?;
+        capture = capture.or(value);
+    }
+    Some(capture.unwrap_or(ip))
+}
+
+// Search for instructions matching `op` between `start_addr` and
+// `end_addr`.
+pub(super) fn resolve_insn(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    op: &InsnOp,
+) -> Result<u64, ResolveErrorKind> {
+    let len = end_addr.saturating_sub(start_addr) as usize;
+    let mut bytes = vec![0x0; len];
+    let read = mem.read(&mut bytes, start_addr, len);
+    bytes.truncate(read);
+
+    for offset in 0..bytes.len() {
+        let ip = start_addr + offset as u64;
+        if let Some(addr) = match_templates(&bytes[offset..], ip, &op.templates) {
+            return Ok(addr);
+        }
+    }
+
+    match read < len {
+        true => Err(ResolveErrorKind::ShortRead {
+            addr: start_addr,
+            len,
+            read,
+        }),
+        false => Err(ResolveErrorKind::NoMatch {
+            start_addr,
+            end_addr,
+        }),
+    }
+}
+
+fn decode_at(
+    mem: &dyn MemReader,
+    addr: u64,
+    f: impl Fn(&Decoder, &Instruction) -> Option<u64>,
+) -> Option<u64> {
+    let mut bytes = [0x0; MAX_INSN_LEN];
+    let read = mem.read(&mut bytes, addr, MAX_INSN_LEN);
+    let mut decoder = Decoder::with_ip(64, &bytes[..read], addr, DecoderOptions::NONE);
+    let insn = decoder.decode();
+    if insn.is_invalid() {
+        return None;
+    }
+    f(&decoder, &insn)
+}
+
+// Find the instruction whose RIP-relative displacement or rel32 branch
+// target starts at `match_addr` and return the address it refers to.
+//
+// The closest instruction start that decodes with its displacement at
+// `match_addr` wins.
+pub(super) fn decode_operand(mem: &dyn MemReader, match_addr: u64) -> Option<u64> {
+    (1..=(MAX_INSN_LEN - 4) as u64)
+        .filter(|k| *k <= match_addr)
+        .find_map(|k| {
+            decode_at(mem, match_addr - k, |decoder, insn| {
+                let offsets = decoder.get_constant_offsets(insn);
+                if offsets.has_displacement()
+                    && offsets.displacement_offset() as u64 == k
+                    && insn.is_ip_rel_memory_operand()
+                {
+                    Some(insn.ip_rel_memory_address())
+                } else if offsets.has_immediate()
+                    && offsets.immediate_offset() as u64 == k
+                    && is_branch(insn.op0_kind())
+                {
+                    Some(insn.near_branch_target())
+                } else {
+                    None
+                }
+            })
+        })
+}
+
+// The target of the near call, jmp or jcc at `addr`.
+pub(super) fn branch_target(mem: &dyn MemReader, addr: u64) -> Option<u64> {
+    decode_at(mem, addr, |_, insn| {
+        match insn.op_count() == 1 && is_branch(insn.op0_kind()) {
+            true => Some(insn.near_branch_target()),
+            false => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::test::TestMemReader;
+    use super::super::{ResolveErrorKind, Signature};
+    use failure::Error;
+
+    fn get_code_mem_reader() -> TestMemReader {
+        #[rustfmt::skip]
+        let r = TestMemReader {
+            mem: vec![
+                // mov rcx, [rip+0x9]
+                0x48, 0x8b, 0x0d, 0x09, 0x00, 0x00, 0x00,
+                // call 0x101a
+                0xe8, 0x0e, 0x00, 0x00, 0x00,
+                // je 0x1010
+                0x74, 0x02,
+                // jmp 0x1010
+                0xeb, 0x00,
+                // mov dword [rip-0xa], 0x11223344
+                0xc7, 0x05, 0xf6, 0xff, 0xff, 0xff, 0x44, 0x33, 0x22, 0x11,
+                // 0x101a
+                0xc3, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        r
+    }
+
+    #[test]
+    fn insn_templates() -> Result<(), Error> {
+        let mem = get_code_mem_reader();
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+        let resolve = |sig: &str| -> Result<u64, Error> {
+            let sig: Signature = sig.parse()?;
+            Ok(sig.resolve(&mem, mem.start_addr, end_addr)?)
+        };
+
+        assert_eq!(resolve("insn(mov rcx, [rip+^]; call ?)")?, 0x1010);
+        assert_eq!(resolve("insn(MOV RCX, ?; call ^)")?, 0x101a);
+        assert_eq!(resolve("insn(call ?; je ^)")?, 0x1010);
+        // Without a capture the op resolves to the first instruction.
+        assert_eq!(resolve("insn(jmp ?; mov [rip-0xa], 0x11223344)")?, 0x100e);
+        assert_eq!(resolve("insn(mov [?], ^)")?, 0x11223344);
+
+        let sig: Signature = "insn(mov rax, [rip+?])".parse()?;
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .map_err(|e| e.kind),
+            Err(ResolveErrorKind::NoMatch {
+                start_addr: 0x1000,
+                end_addr
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn insn_operands() -> Result<(), Error> {
+        let mem = get_code_mem_reader();
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        // The displacement is followed by an immediate so its end can only be
+        // found by decoding the instruction.
+        let sig: Signature = "asm(c705^^^^^^^^, end=auto)".parse()?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1010));
+        assert_eq!(sig.to_string(), "asm(c705^^^^^^^^, end=auto)");
+        // Without it the instruction ends after the displacement, as it
+        // does without the feature.
+        let sig: Signature = "asm(c705^^^^^^^^)".parse()?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x100c));
+        let sig: Signature = "asm(488b0d^^^^^^^^, end=auto)".parse()?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1010));
+        let sig: Signature = "asm(c3^^, end=auto)".parse()?;
+        assert_eq!(
+            sig.resolve(&mem, mem.start_addr, end_addr)
+                .map_err(|e| e.kind),
+            Err(ResolveErrorKind::NoOperand { addr: 0x101b })
+        );
+
+        // Short and conditional branches can be followed.
+        let sig: Signature = "asm(^^02eb, raw); follow()".parse()?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1010));
+        let sig: Signature = "asm(^^00c705, raw); follow()".parse()?;
+        assert_eq!(sig.resolve(&mem, mem.start_addr, end_addr), Ok(0x1010));
+        Ok(())
+    }
+
+    #[test]
+    fn insn_display_round_trip() -> Result<(), Error> {
+        let sigs = vec![
+            "insn(mov rcx, [rip+^]; call ?)",
+            "insn(?; mov [rbp-0x8], 0x10); ptr(0)",
+            "insn(cmp [rax], -0x1; jne ^)",
+            "insn(ret)",
+        ];
+        for sig in sigs {
+            assert_eq!(sig.parse::<Signature>()?.to_string(), sig);
+        }
+
+        assert!("insn()".parse::<Signature>().is_err());
+        assert!("insn(mov rcx,)".parse::<Signature>().is_err());
+        assert!("insn(mov [rip+])".parse::<Signature>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+use super::chunked::{read_u64, PAGE_SIZE};
+use super::MemReader;
+use failure::{format_err, Error};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"MSSNAP\x00\x01";
+
+/// A contiguous block of captured memory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotRegion {
+    pub start_addr: u64,
+    pub data: Vec<u8>,
+}
+
+impl SnapshotRegion {
+    pub fn end_addr(&self) -> u64 {
+        self.start_addr + self.data.len() as u64
+    }
+}
+
+/// A `MemReader` backed by memory captured from another `MemReader`.
+///
+/// Snapshots can be written to and read back from disk so signatures can be
+/// checked against a dump of a process without it running.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Captured regions sorted by address.
+    pub regions: Vec<SnapshotRegion>,
+    /// Base addresses reported by `module_base`, keyed by module name.
+    pub modules: HashMap<String, u64>,
+}
+
+impl Snapshot {
+    /// Capture `start_addr..end_addr` from `mem` along with the base address
+    /// of each module in `modules` that `mem` knows about.
+    pub fn capture(mem: &dyn MemReader, start_addr: u64, end_addr: u64, modules: &[&str]) -> Self {
+        let mut snapshot = Snapshot::default();
+        let mut addr = start_addr;
+        while addr < end_addr {
+            // Read up to the next page boundary.
+            let len = ((addr / PAGE_SIZE + 1) * PAGE_SIZE).min(end_addr) - addr;
+            let mut buf = vec![0x0; len as usize];
+            let read = mem.read(&mut buf, addr, len as usize);
+            buf.truncate(read);
+            snapshot.add(addr, &buf);
+            addr += len;
+        }
+        for name in modules {
+            if let Some(base) = mem.module_base(name) {
+                snapshot.modules.insert(name.to_string(), base);
+            }
+        }
+        snapshot
+    }
+
+    // Append `data` at `addr`, merging it with the last region if adjacent.
+    fn add(&mut self, addr: u64, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        match self.regions.last_mut() {
+            Some(region) if region.end_addr() == addr => region.data.extend_from_slice(data),
+            _ => self.regions.push(SnapshotRegion {
+                start_addr: addr,
+                data: data.to_vec(),
+            }),
+        }
+    }
+
+    /// The lowest captured address.
+    pub fn start_addr(&self) -> u64 {
+        self.regions.first().map_or(0, |r| r.start_addr)
+    }
+
+    /// One past the highest captured address.
+    pub fn end_addr(&self) -> u64 {
+        self.regions.last().map_or(0, |r| r.end_addr())
+    }
+
+    /// Read a snapshot written by `write`.
+    pub fn new(reader: &mut impl Read) -> Result<Snapshot, Error> {
+        let mut magic = [0x0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format_err!("not a memscanner snapshot"));
+        }
+
+        let mut snapshot = Snapshot::default();
+        for _ in 0..read_u64(reader)? {
+            let len = read_u64(reader)?;
+            let name = String::from_utf8(read_bytes(reader, len)?)?;
+            snapshot.modules.insert(name, read_u64(reader)?);
+        }
+        for _ in 0..read_u64(reader)? {
+            let start_addr = read_u64(reader)?;
+            let len = read_u64(reader)?;
+            // `end_addr` and `read` rely on regions not wrapping around.
+            if start_addr.checked_add(len).is_none() {
+                return Err(format_err!(
+                    "snapshot region 0x{:x} out of range",
+                    start_addr
+                ));
+            }
+            let data = read_bytes(reader, len)?;
+            if start_addr < snapshot.end_addr() {
+                return Err(format_err!(
+                    "snapshot region 0x{:x} out of order",
+                    start_addr
+                ));
+            }
+            snapshot.regions.push(SnapshotRegion { start_addr, data });
+        }
+        Ok(snapshot)
+    }
+
+    /// Write the snapshot.  The output can be read back with `new`.
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.write_all(MAGIC)?;
+
+        // Sort modules so the output is stable.
+        let mut modules: Vec<_> = self.modules.iter().collect();
+        modules.sort();
+        writer.write_all(&(modules.len() as u64).to_le_bytes())?;
+        for (name, base) in modules {
+            writer.write_all(&(name.len() as u64).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&base.to_le_bytes())?;
+        }
+
+        writer.write_all(&(self.regions.len() as u64).to_le_bytes())?;
+        for region in &self.regions {
+            writer.write_all(&region.start_addr.to_le_bytes())?;
+            writer.write_all(&(region.data.len() as u64).to_le_bytes())?;
+            writer.write_all(&region.data)?;
+        }
+        Ok(())
+    }
+}
+
+// Read `len` bytes without trusting `len` for the allocation, so a corrupt
+// length fails at the end of the file instead of allocating it up front.
+fn read_bytes(reader: &mut impl Read, len: u64) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(format_err!("snapshot truncated"));
+    }
+    Ok(buf)
+}
+
+impl MemReader for Snapshot {
+    fn read(&self, buf: &mut [u8], addr: u64, len: usize) -> usize {
+        // Find the last region starting at or before `addr`.
+        let index = match self.regions.binary_search_by_key(&addr, |r| r.start_addr) {
+            Ok(i) => i,
+            Err(0) => return 0,
+            Err(i) => i - 1,
+        };
+        let region = &self.regions[index];
+        if addr >= region.end_addr() {
+            return 0;
+        }
+        let offset = (addr - region.start_addr) as usize;
+        let read_len = len.min(buf.len()).min(region.data.len() - offset);
+        buf[..read_len].copy_from_slice(&region.data[offset..offset + read_len]);
+        read_len
+    }
+
+    fn module_base(&self, name: &str) -> Option<u64> {
+        self.modules.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::TestMemReader;
+    use super::*;
+
+    #[test]
+    fn capture_round_trip() -> Result<(), Error> {
+        let mut mem = TestMemReader {
+            mem: (0..0x20).collect(),
+            start_addr: 0x1ff0,
+            ..Default::default()
+        };
+        mem.modules.insert("game.exe".to_string(), 0x1ff0);
+
+        // The range extends past the end of `mem`.
+        let snapshot = Snapshot::capture(&mem, 0x1ff0, 0x3000, &["game.exe", "other.dll"]);
+        assert_eq!(snapshot.regions.len(), 1);
+        assert_eq!(
+            (snapshot.start_addr(), snapshot.end_addr()),
+            (0x1ff0, 0x2010)
+        );
+        assert_eq!(snapshot.module_base("game.exe"), Some(0x1ff0));
+        assert_eq!(snapshot.module_base("other.dll"), None);
+
+        let mut buf = Vec::new();
+        snapshot.write(&mut buf)?;
+        let read_snapshot = Snapshot::new(&mut buf.as_slice())?;
+        assert_eq!(read_snapshot, snapshot);
+
+        assert!(Snapshot::new(&mut &buf[1..]).is_err());
+        assert!(Snapshot::new(&mut &buf[..buf.len() - 1]).is_err());
+
+        // A region claiming to be larger than the file.
+        let mut huge = MAGIC.to_vec();
+        for value in &[0, 1, 0x1000, u64::MAX] {
+            huge.extend_from_slice(&value.to_le_bytes());
+        }
+        huge.extend_from_slice(&[0x0; 16]);
+        assert!(Snapshot::new(&mut huge.as_slice()).is_err());
+
+        // A region wrapping around the end of the address space.
+        let mut wrap = MAGIC.to_vec();
+        for value in &[0, 1, 0xffff_ffff_ffff_fff0, 0x20u64] {
+            wrap.extend_from_slice(&value.to_le_bytes());
+        }
+        wrap.extend_from_slice(&[0x0; 0x20]);
+        let err = Snapshot::new(&mut wrap.as_slice()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "snapshot region 0xfffffffffffffff0 out of range"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read() {
+        let snapshot = Snapshot {
+            regions: vec![
+                SnapshotRegion {
+                    start_addr: 0x1000,
+                    data: vec![0x00, 0x11, 0x22, 0x33],
+                },
+                SnapshotRegion {
+                    start_addr: 0x2000,
+                    data: vec![0x44, 0x55],
+                },
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(snapshot.read_u8(0x1002), Some(0x22));
+        assert_eq!(snapshot.read_u32(0x1000), Some(0x33221100));
+        assert_eq!(snapshot.read_u32(0x1002), None);
+        assert_eq!(snapshot.read_u8(0x2001), Some(0x55));
+        assert!(!snapshot.is_mapped(0xfff));
+        assert!(!snapshot.is_mapped(0x1004));
+        assert!(!snapshot.is_mapped(0x2002));
+    }
+}
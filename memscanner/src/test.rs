@@ -1,23 +1,31 @@
-use super::MemReader;
+use super::{Address, MemReader};
+use std::collections::HashMap;
 
 /// A `MemReader` implementation that is backed by a buffer.  Useful for
 /// writing tests.
+#[derive(Default)]
 pub struct TestMemReader {
     pub mem: Vec<u8>,
     pub start_addr: u64,
+    /// Base addresses reported by `module_base`, keyed by module name.
+    pub modules: HashMap<String, u64>,
 }
 
 impl MemReader for TestMemReader {
     fn read(&self, buf: &mut [u8], addr: u64, len: usize) -> usize {
-        let index = (addr - self.start_addr) as usize;
-        let read_len = if index + len > self.mem.len() {
-            self.mem.len() - index
-        } else {
-            len
+        // Reads outside of `mem` are short, like unmapped process memory.
+        let index = match Address(addr).offset_from(Address(self.start_addr)) {
+            Some(index) if index < self.mem.len() as u64 => index as usize,
+            _ => return 0,
         };
+        let read_len = len.min(buf.len()).min(self.mem.len() - index);
 
-        buf.copy_from_slice(&self.mem[index..(index + read_len)]);
+        buf[..read_len].copy_from_slice(&self.mem[index..(index + read_len)]);
 
         read_len
     }
+
+    fn module_base(&self, name: &str) -> Option<u64> {
+        self.modules.get(name).cloned()
+    }
 }
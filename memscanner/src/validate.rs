@@ -0,0 +1,138 @@
+use super::signature::ResolveError;
+use super::{MemReader, TypeConfig};
+use std::fmt;
+
+/// The result of checking one `TypeConfig`'s signature against memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureReport {
+    pub name: String,
+    /// The alternative that resolved, or the first one if none did.
+    pub alternative: usize,
+    /// Every location matched by the first op of `alternative`, or `None`
+    /// if it doesn't start with an `asm()` search.
+    pub matches: Option<Vec<u64>>,
+    /// The final address the signature resolved to.
+    pub resolved: Result<u64, ResolveError>,
+    /// Whether `resolved` points at readable memory.
+    pub mapped: bool,
+}
+
+impl SignatureReport {
+    /// A signature is healthy if it resolves to mapped memory and its first
+    /// search, if any, is unique.
+    pub fn is_healthy(&self) -> bool {
+        let unique = !matches!(&self.matches, Some(m) if m.len() != 1);
+        unique && self.resolved.is_ok() && self.mapped
+    }
+}
+
+impl fmt::Display for SignatureReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.is_healthy() { "ok" } else { "FAIL" };
+        write!(
+            f,
+            "{}: {} alternative {}",
+            self.name, status, self.alternative
+        )?;
+        if let Some(matches) = &self.matches {
+            write!(f, ", {} matches", matches.len())?;
+            if let Some(addr) = matches.first() {
+                write!(f, " (first at 0x{:x})", addr)?;
+            }
+        }
+        match &self.resolved {
+            Ok(addr) if self.mapped => write!(f, ", resolved to 0x{:x}", addr),
+            Ok(addr) => write!(f, ", resolved to unmapped 0x{:x}", addr),
+            Err(e) => write!(f, ", {}", e),
+        }
+    }
+}
+
+/// Check the signature of each named config in `configs` against `mem`
+/// between `start_addr` and `end_addr`.
+pub fn validate(
+    mem: &dyn MemReader,
+    start_addr: u64,
+    end_addr: u64,
+    configs: &[(String, TypeConfig)],
+) -> Vec<SignatureReport> {
+    configs
+        .iter()
+        .map(|(name, config)| {
+            let signature = &config.signature;
+            let (alternative, resolved) =
+                match signature.resolve_alternative(mem, start_addr, end_addr) {
+                    Ok((alternative, addr)) => (alternative, Ok(addr)),
+                    Err(e) => (e.alternative, Err(e)),
+                };
+            SignatureReport {
+                name: name.clone(),
+                alternative,
+                matches: signature.matches(mem, start_addr, end_addr, alternative),
+                mapped: matches!(resolved, Ok(addr) if mem.is_mapped(addr)),
+                resolved,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::TestMemReader;
+    use super::*;
+    use failure::Error;
+
+    fn config(signature: &str) -> Result<TypeConfig, Error> {
+        let text = format!("{{ signature: {:?}, fields: {{}} }}", signature);
+        TypeConfig::new(&mut text.as_bytes())
+    }
+
+    #[test]
+    fn validate_configs() -> Result<(), Error> {
+        #[rustfmt::skip]
+        let mem = TestMemReader {
+            mem: vec![
+                0xff, 0xff, 0xff, 0xff, 0x00, 0x11, 0x22, 0x33,
+                0x04, 0x00, 0x00, 0x00, 0x44, 0x55, 0x66, 0x77,
+                0x00, 0x11, 0x22, 0x33, 0xcc, 0xdd, 0xee, 0xff,
+            ],
+            start_addr: 0x1000,
+            ..Default::default()
+        };
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let configs = vec![
+            ("unique".to_string(), config("asm(00112233^^^^^^^^44)")?),
+            ("duplicate".to_string(), config("asm(00112233^^^^^^^^)")?),
+            ("unmapped".to_string(), config("base+0x100")?),
+            ("missing".to_string(), config("asm(8899) | asm(9988)")?),
+        ];
+        let reports = validate(&mem, mem.start_addr, end_addr, &configs);
+
+        assert_eq!(reports[0].matches, Some(vec![0x1008]));
+        assert_eq!(reports[0].resolved, Ok(0x1010));
+        assert!(reports[0].is_healthy());
+        assert_eq!(
+            reports[0].to_string(),
+            "unique: ok alternative 0, 1 matches (first at 0x1008), resolved to 0x1010"
+        );
+
+        assert_eq!(reports[1].matches, Some(vec![0x1008, 0x1014]));
+        assert!(!reports[1].is_healthy());
+
+        assert_eq!(reports[2].matches, None);
+        assert_eq!(reports[2].resolved, Ok(0x1100));
+        assert!(!reports[2].mapped);
+        assert!(!reports[2].is_healthy());
+
+        assert_eq!(reports[3].alternative, 0);
+        assert_eq!(reports[3].matches, Some(vec![]));
+        assert!(reports[3].resolved.is_err());
+        assert_eq!(
+            reports[3].to_string(),
+            "missing: FAIL alternative 0, 0 matches, \
+             asm(8899) failed at 0x1000 (alternative 0, op 0): no match in 0x1000..0x1018"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,633 @@
+use super::chunked::{read_u64, ValueReader, CHUNK_SIZE};
+use super::MemReader;
+use failure::{format_err, Error};
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+// Keeps the candidate files of concurrent scans apart.
+static NEXT_SCAN_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The type of the values a `ValueScan` looks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    /// A string of this many bytes.
+    String(usize),
+}
+
+/// A value read from or searched for in memory.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum Value {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    String(String),
+}
+
+macro_rules! decode_value {
+    ($variant: ident, $type: ty, $bytes: expr) => {
+        Value::$variant(<$type>::from_ne_bytes($bytes.try_into().unwrap()))
+    };
+}
+
+impl ValueType {
+    /// The size of a value in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            ValueType::U8 | ValueType::I8 => 1,
+            ValueType::U16 | ValueType::I16 => 2,
+            ValueType::U32 | ValueType::I32 | ValueType::F32 => 4,
+            ValueType::U64 | ValueType::I64 | ValueType::F64 => 8,
+            ValueType::String(len) => len,
+        }
+    }
+
+    // `bytes` must be exactly `size()` bytes long.
+    fn decode(self, bytes: &[u8]) -> Value {
+        match self {
+            ValueType::U8 => Value::U8(bytes[0]),
+            ValueType::I8 => Value::I8(bytes[0] as i8),
+            ValueType::U16 => decode_value!(U16, u16, bytes),
+            ValueType::I16 => decode_value!(I16, i16, bytes),
+            ValueType::U32 => decode_value!(U32, u32, bytes),
+            ValueType::I32 => decode_value!(I32, i32, bytes),
+            ValueType::U64 => decode_value!(U64, u64, bytes),
+            ValueType::I64 => decode_value!(I64, i64, bytes),
+            ValueType::F32 => decode_value!(F32, f32, bytes),
+            ValueType::F64 => decode_value!(F64, f64, bytes),
+            ValueType::String(_) => Value::String(String::from_utf8_lossy(bytes).to_string()),
+        }
+    }
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::U8(_) => ValueType::U8,
+            Value::I8(_) => ValueType::I8,
+            Value::U16(_) => ValueType::U16,
+            Value::I16(_) => ValueType::I16,
+            Value::U32(_) => ValueType::U32,
+            Value::I32(_) => ValueType::I32,
+            Value::U64(_) => ValueType::U64,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+            Value::String(s) => ValueType::String(s.len()),
+        }
+    }
+}
+
+/// What the first scan of a `ValueScan` keeps.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FirstScan {
+    /// Values equal to this one.
+    Exact(Value),
+    /// Values between the two, inclusive.
+    Between(Value, Value),
+    /// Every value of the type, to be narrowed down by later scans.
+    Unknown(ValueType),
+}
+
+/// What a later scan of a `ValueScan` keeps, compared to the previous scan.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NextScan {
+    Exact(Value),
+    Between(Value, Value),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+/// An address still matching a `ValueScan` and its value at the last scan.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub addr: u64,
+    pub value: Value,
+}
+
+/// A Cheat Engine style search for an unknown address by its value.
+///
+/// A first scan finds every address holding a matching value and later
+/// scans narrow those down as the value changes.  Candidates are kept in a
+/// file in the directory passed to `first_scan` rather than in memory so
+/// scans of large processes are practical.  The file is removed when the
+/// `ValueScan` is dropped.
+pub struct ValueScan {
+    value_type: ValueType,
+    // The distance between tested addresses.
+    stride: u64,
+    dir: PathBuf,
+    id: usize,
+    generation: u32,
+    count: u64,
+}
+
+impl ValueScan {
+    /// Scan `ranges` of `mem` for values matching `scan`.
+    ///
+    /// Only addresses that are a multiple of `align` are tested.  It
+    /// defaults to the size of the value for numbers and to 1 for strings.
+    pub fn first_scan(
+        mem: &dyn MemReader,
+        ranges: &[(u64, u64)],
+        scan: &FirstScan,
+        align: Option<u64>,
+        dir: &Path,
+    ) -> Result<ValueScan, Error> {
+        let value_type = match scan {
+            FirstScan::Exact(v) => v.value_type(),
+            FirstScan::Between(min, max) => range_type(min, max)?,
+            FirstScan::Unknown(t) => *t,
+        };
+        let size = value_type.size();
+        if size == 0 || size as u64 > CHUNK_SIZE {
+            return Err(format_err!("can't scan for {} byte values", size));
+        }
+        let stride = align.unwrap_or(match value_type {
+            ValueType::String(_) => 1,
+            t => t.size() as u64,
+        });
+        if stride == 0 || stride > CHUNK_SIZE {
+            return Err(format_err!("bad alignment {}", stride));
+        }
+
+        let mut scan_state = ValueScan {
+            value_type,
+            stride,
+            dir: dir.to_path_buf(),
+            id: NEXT_SCAN_ID.fetch_add(1, AtomicOrdering::Relaxed),
+            generation: 0,
+            count: 0,
+        };
+        let mut writer = BlockWriter::new(File::create(scan_state.path())?, size, stride);
+        for &(start_addr, end_addr) in ranges {
+            let mut values = ValueReader::new(mem, start_addr, end_addr, size, stride);
+            while let Some((addr, bytes)) = values.next_value() {
+                if first_scan_matches(scan, &value_type.decode(bytes)) {
+                    writer.push(addr, bytes)?;
+                }
+            }
+        }
+        scan_state.count = writer.finish()?;
+        Ok(scan_state)
+    }
+
+    /// Re-read every candidate and keep those matching `scan`.
+    ///
+    /// Returns the number of candidates left.  Candidates that can no longer
+    /// be read are dropped.
+    pub fn next_scan(&mut self, mem: &dyn MemReader, scan: &NextScan) -> Result<u64, Error> {
+        match scan {
+            NextScan::Exact(v) => self.check_type(v)?,
+            NextScan::Between(min, max) => {
+                self.check_type(min)?;
+                range_type(min, max)?;
+            }
+            NextScan::Increased | NextScan::Decreased => {
+                if let ValueType::String(_) = self.value_type {
+                    return Err(format_err!("strings can only be compared for equality"));
+                }
+            }
+            NextScan::Changed | NextScan::Unchanged => {}
+        }
+
+        // The new candidates replace the old ones only once they are all
+        // written, so a failed scan leaves the last good one in place.
+        let new_path = self.generation_path(self.generation + 1);
+        match self.rescan(mem, scan, &new_path) {
+            Ok(count) => {
+                let old_path = self.path();
+                self.generation += 1;
+                self.count = count;
+                fs::remove_file(old_path)?;
+                Ok(count)
+            }
+            Err(e) => {
+                let _ = fs::remove_file(new_path);
+                Err(e)
+            }
+        }
+    }
+
+    // Write the candidates still matching `scan` to `path`.
+    fn rescan(&self, mem: &dyn MemReader, scan: &NextScan, path: &Path) -> Result<u64, Error> {
+        let size = self.value_type.size();
+        let mut reader = BlockReader::new(File::open(self.path())?, size, self.count);
+        let mut writer = BlockWriter::new(File::create(path)?, size, self.stride);
+        while let Some((start_addr, values)) = reader.next_block()? {
+            let count = (values.len() / size) as u64;
+            let end_addr = start_addr.saturating_add((count - 1) * self.stride + size as u64);
+            // An unreadable page only drops the candidates on it.
+            let mut new_values = ValueReader::new(mem, start_addr, end_addr, size, self.stride);
+            while let Some((addr, bytes)) = new_values.next_value() {
+                let i = ((addr - start_addr) / self.stride) as usize;
+                let old_bytes = &values[i * size..(i + 1) * size];
+                if next_scan_matches(scan, self.value_type, old_bytes, bytes) {
+                    writer.push(addr, bytes)?;
+                }
+            }
+        }
+        writer.finish()
+    }
+
+    /// The number of candidates left.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+
+    /// Read every candidate from disk.
+    pub fn candidates(&self) -> Result<Vec<Candidate>, Error> {
+        let size = self.value_type.size();
+        let mut reader = BlockReader::new(File::open(self.path())?, size, self.count);
+        let mut candidates = vec![];
+        while let Some((start_addr, values)) = reader.next_block()? {
+            for (i, bytes) in values.chunks(size).enumerate() {
+                candidates.push(Candidate {
+                    addr: start_addr + i as u64 * self.stride,
+                    value: self.value_type.decode(bytes),
+                });
+            }
+        }
+        Ok(candidates)
+    }
+
+    fn path(&self) -> PathBuf {
+        self.generation_path(self.generation)
+    }
+
+    fn generation_path(&self, generation: u32) -> PathBuf {
+        self.dir.join(format!(
+            "memscanner-{}-{}-{}.candidates",
+            std::process::id(),
+            self.id,
+            generation
+        ))
+    }
+
+    fn check_type(&self, value: &Value) -> Result<(), Error> {
+        match value.value_type() == self.value_type {
+            true => Ok(()),
+            false => Err(format_err!(
+                "expected a {:?} value, got {:?}",
+                self.value_type,
+                value
+            )),
+        }
+    }
+}
+
+impl Drop for ValueScan {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.path());
+    }
+}
+
+fn range_type(min: &Value, max: &Value) -> Result<ValueType, Error> {
+    match (min.value_type(), max.value_type()) {
+        (ValueType::String(_), _) => Err(format_err!("strings can't be scanned by range")),
+        (a, b) if a != b => Err(format_err!("range of {:?} to {:?}", a, b)),
+        (a, _) => Ok(a),
+    }
+}
+
+fn in_range(value: &Value, min: &Value, max: &Value) -> bool {
+    value >= min && value <= max
+}
+
+fn first_scan_matches(scan: &FirstScan, value: &Value) -> bool {
+    match scan {
+        FirstScan::Exact(v) => value == v,
+        FirstScan::Between(min, max) => in_range(value, min, max),
+        FirstScan::Unknown(_) => true,
+    }
+}
+
+fn next_scan_matches(scan: &NextScan, value_type: ValueType, old: &[u8], new: &[u8]) -> bool {
+    let new_value = || value_type.decode(new);
+    let ordering = || new_value().partial_cmp(&value_type.decode(old));
+    match scan {
+        NextScan::Exact(v) => new_value() == *v,
+        NextScan::Between(min, max) => in_range(&new_value(), min, max),
+        // Bytes rather than values are compared so a NaN that stays the same
+        // is unchanged and a zero changing sign is a change.
+        NextScan::Changed => new != old,
+        NextScan::Unchanged => new == old,
+        NextScan::Increased => ordering() == Some(Ordering::Greater),
+        NextScan::Decreased => ordering() == Some(Ordering::Less),
+    }
+}
+
+// Candidates are stored as blocks of evenly spaced addresses.  Each block is
+// the address of its first candidate and the number of candidates as
+// little endian u64s, followed by each candidate's value.  A full scan of a
+// region costs little more than a copy of it.
+struct BlockWriter<W: Write> {
+    out: BufWriter<W>,
+    size: usize,
+    stride: u64,
+    start_addr: u64,
+    values: Vec<u8>,
+    count: u64,
+}
+
+impl<W: Write> BlockWriter<W> {
+    fn new(out: W, size: usize, stride: u64) -> Self {
+        BlockWriter {
+            out: BufWriter::new(out),
+            size,
+            stride,
+            start_addr: 0,
+            values: vec![],
+            count: 0,
+        }
+    }
+
+    // Blocks are kept to `CHUNK_SIZE` bytes of values and of memory so the
+    // next scan never reads far past the candidates it needs.
+    fn push(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        let len = (self.values.len() / self.size) as u64;
+        if len > 0
+            && (Some(addr) != self.start_addr.checked_add(len * self.stride)
+                || addr - self.start_addr + self.size as u64 > CHUNK_SIZE)
+        {
+            self.flush()?;
+        }
+        if self.values.is_empty() {
+            self.start_addr = addr;
+        }
+        self.values.extend_from_slice(value);
+        self.count += 1;
+        if self.values.len() as u64 >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.values.is_empty() {
+            return Ok(());
+        }
+        let len = (self.values.len() / self.size) as u64;
+        self.out.write_all(&self.start_addr.to_le_bytes())?;
+        self.out.write_all(&len.to_le_bytes())?;
+        self.out.write_all(&self.values)?;
+        self.values.clear();
+        Ok(())
+    }
+
+    // Returns the number of candidates written.
+    fn finish(mut self) -> Result<u64, Error> {
+        self.flush()?;
+        self.out.flush()?;
+        Ok(self.count)
+    }
+}
+
+struct BlockReader<R: Read> {
+    input: BufReader<R>,
+    size: usize,
+    // Candidates not yet read.
+    remaining: u64,
+}
+
+impl<R: Read> BlockReader<R> {
+    fn new(input: R, size: usize, count: u64) -> Self {
+        BlockReader {
+            input: BufReader::new(input),
+            size,
+            remaining: count,
+        }
+    }
+
+    // Returns the address of the block's first candidate and its values.
+    fn next_block(&mut self) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let start_addr = read_u64(&mut self.input)?;
+        let len = read_u64(&mut self.input)?;
+        if len == 0 || len > self.remaining {
+            return Err(format_err!("corrupt candidate block at 0x{:x}", start_addr));
+        }
+        let mut values = vec![0x0; len as usize * self.size];
+        self.input.read_exact(&mut values)?;
+        self.remaining -= len;
+        Ok(Some((start_addr, values)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::snapshot::{Snapshot, SnapshotRegion};
+    use super::super::test::TestMemReader;
+    use super::*;
+
+    fn addrs(scan: &ValueScan) -> Vec<u64> {
+        scan.candidates().unwrap().iter().map(|c| c.addr).collect()
+    }
+
+    fn get_mem_reader() -> TestMemReader {
+        let mut mem = vec![0x0; 0x40];
+        mem[0x00..0x04].copy_from_slice(&100u32.to_ne_bytes());
+        mem[0x08..0x0c].copy_from_slice(&100u32.to_ne_bytes());
+        mem[0x10..0x14].copy_from_slice(&50u32.to_ne_bytes());
+        // Unaligned.
+        mem[0x15..0x19].copy_from_slice(&100u32.to_ne_bytes());
+        mem[0x20..0x26].copy_from_slice(b"player");
+        TestMemReader {
+            mem,
+            start_addr: 0x1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exact_then_next() -> Result<(), Error> {
+        let dir = std::env::temp_dir();
+        let mut mem = get_mem_reader();
+        // The range extends past the end of `mem`.
+        let ranges = [(0x1000, 0x3000)];
+
+        let first = FirstScan::Exact(Value::U32(100));
+        let mut scan = ValueScan::first_scan(&mem, &ranges, &first, None, &dir)?;
+        assert_eq!(addrs(&scan), vec![0x1000, 0x1008]);
+        let unaligned = ValueScan::first_scan(&mem, &ranges, &first, Some(1), &dir)?;
+        assert_eq!(addrs(&unaligned), vec![0x1000, 0x1008, 0x1015]);
+
+        mem.mem[0x00] += 1;
+        assert_eq!(scan.next_scan(&mem, &NextScan::Unchanged)?, 1);
+        assert_eq!(addrs(&scan), vec![0x1008]);
+
+        mem.mem[0x08] -= 1;
+        assert_eq!(scan.next_scan(&mem, &NextScan::Decreased)?, 1);
+        assert_eq!(
+            scan.candidates()?,
+            vec![Candidate {
+                addr: 0x1008,
+                value: Value::U32(99)
+            }]
+        );
+        assert!(scan
+            .next_scan(&mem, &NextScan::Exact(Value::I32(99)))
+            .is_err());
+        assert_eq!(scan.next_scan(&mem, &NextScan::Exact(Value::U32(98)))?, 0);
+        assert!(scan.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_then_changed() -> Result<(), Error> {
+        let dir = std::env::temp_dir();
+        let mut mem = get_mem_reader();
+        let ranges = [(0x1000, 0x1010), (0x1030, 0x1040)];
+
+        let first = FirstScan::Unknown(ValueType::U32);
+        let mut scan = ValueScan::first_scan(&mem, &ranges, &first, None, &dir)?;
+        assert_eq!(scan.len(), 8);
+
+        mem.mem[0x04] = 0x1;
+        mem.mem[0x34] = 0x2;
+        assert_eq!(scan.next_scan(&mem, &NextScan::Changed)?, 2);
+        assert_eq!(addrs(&scan), vec![0x1004, 0x1034]);
+        assert_eq!(scan.next_scan(&mem, &NextScan::Increased)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn float_changes() -> Result<(), Error> {
+        let dir = std::env::temp_dir();
+        let mut mem = get_mem_reader();
+        mem.mem[0x00..0x04].copy_from_slice(&f32::NAN.to_ne_bytes());
+        mem.mem[0x04..0x08].copy_from_slice(&0.0f32.to_ne_bytes());
+        let ranges = [(0x1000, 0x1008)];
+
+        let first = FirstScan::Unknown(ValueType::F32);
+        let mut scan = ValueScan::first_scan(&mem, &ranges, &first, None, &dir)?;
+        assert_eq!(scan.next_scan(&mem, &NextScan::Unchanged)?, 2);
+
+        mem.mem[0x04..0x08].copy_from_slice(&(-0.0f32).to_ne_bytes());
+        assert_eq!(scan.next_scan(&mem, &NextScan::Changed)?, 1);
+        assert_eq!(addrs(&scan), vec![0x1004]);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_next_scan() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("memscanner-test-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let mem = get_mem_reader();
+        let ranges = [(0x1000, 0x1040)];
+
+        let first = FirstScan::Unknown(ValueType::U32);
+        let mut scan = ValueScan::first_scan(&mem, &ranges, &first, None, &dir)?;
+        let files = || fs::read_dir(&dir).map(|d| d.count());
+        assert_eq!(files()?, 1);
+
+        // Truncate the candidates so the next scan fails part way through.
+        let path = scan.path();
+        let len = fs::metadata(&path)?.len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(len - 1)?;
+        assert!(scan.next_scan(&mem, &NextScan::Unchanged).is_err());
+        assert_eq!(scan.path(), path);
+        assert_eq!(files()?, 1);
+
+        drop(scan);
+        assert_eq!(files()?, 0);
+        fs::remove_dir(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn unreadable_page() -> Result<(), Error> {
+        let dir = std::env::temp_dir();
+        let region = |start_addr, len| SnapshotRegion {
+            start_addr,
+            data: vec![0x0; len],
+        };
+        let mut mem = Snapshot {
+            regions: vec![region(0x10000, 0x3000)],
+            ..Default::default()
+        };
+
+        let first = FirstScan::Unknown(ValueType::U32);
+        let mut scan = ValueScan::first_scan(&mem, &[(0x10000, 0x13000)], &first, None, &dir)?;
+        assert_eq!(scan.len(), 0xc00);
+
+        // Only the candidates on the freed page are dropped.
+        mem.regions = vec![region(0x10000, 0x1000), region(0x12000, 0x1000)];
+        assert_eq!(scan.next_scan(&mem, &NextScan::Unchanged)?, 0x800);
+        let addrs = addrs(&scan);
+        assert_eq!(addrs[0x3ff..0x401], [0x10ffc, 0x12000]);
+        Ok(())
+    }
+
+    #[test]
+    fn block_span() -> Result<(), Error> {
+        let mut out = vec![];
+        let mut writer = BlockWriter::new(&mut out, 4, 0x8000);
+        for addr in &[0x0, 0x8000, 0x10000] {
+            writer.push(*addr, &[0x0; 4])?;
+        }
+        assert_eq!(writer.finish()?, 3);
+
+        // The third candidate would take the block past `CHUNK_SIZE` bytes.
+        let mut reader = BlockReader::new(out.as_slice(), 4, 3);
+        assert_eq!(reader.next_block()?, Some((0x0, vec![0x0; 8])));
+        assert_eq!(reader.next_block()?, Some((0x10000, vec![0x0; 4])));
+        assert_eq!(reader.next_block()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn ranges_and_strings() -> Result<(), Error> {
+        let dir = std::env::temp_dir();
+        let mem = get_mem_reader();
+        let ranges = [(0x1000, 0x1040)];
+
+        let first = FirstScan::Between(Value::I32(40), Value::I32(60));
+        let scan = ValueScan::first_scan(&mem, &ranges, &first, None, &dir)?;
+        assert_eq!(addrs(&scan), vec![0x1010]);
+
+        let first = FirstScan::Exact(Value::String("player".to_string()));
+        let mut scan = ValueScan::first_scan(&mem, &ranges, &first, None, &dir)?;
+        assert_eq!(addrs(&scan), vec![0x1020]);
+        assert!(scan.next_scan(&mem, &NextScan::Increased).is_err());
+
+        let first = FirstScan::Between(Value::I32(40), Value::U32(60));
+        assert!(ValueScan::first_scan(&mem, &ranges, &first, None, &dir).is_err());
+        Ok(())
+    }
+}
@@ -18,27 +18,44 @@ fn scannable_impl(ctx: &mut Context, ast: &syn::DeriveInput) -> Option<TokenStre
     let name = &ast.ident;
     let name_str = syn::LitStr::new(&format!("{}", name), ast.ident.span());
 
-    let mut offset_code = quote! {};
+    let mut field_code = quote! {};
+    let mut clone_code = quote! {};
     let mut read_code = quote! {use memscanner::ScannableValue;};
     for f in data.fields.iter() {
         let ident = f.ident.as_ref().unwrap();
+        let ty = &f.ty;
 
         // Construct new identifiers.
         let ident_str = syn::LitStr::new(&format!("{}", ident), f.ty.span());
-        let offset = format_ident!("{}_offset", ident);
+        let field = format_ident!("{}_field", ident);
 
-        // The code that looks up the field's offset in the config and saves it.
-        offset_code.extend(quote! {
-            let #offset = config
+        // The code that looks up the field's config, checks it against the
+        // field's type and saves it.
+        field_code.extend(quote! {
+            let #field = config
                 .fields
                 .get(#ident_str)
                 .ok_or(format_err!("{} field offset not found", #ident_str))?
                 .clone();
+            memscanner::macro_helpers::check_field(
+                #ident_str,
+                &#field,
+                <#ty as memscanner::ScannableValue<#ty>>::field_type())?;
+        });
+
+        // The code that gives each scanner its own copy of the field's config.
+        clone_code.extend(quote! {
+            let #field = #field.clone();
         });
 
         // The code that reads the field's value and stores it in the object.
+        // Null field pointers leave the field as it was.
         read_code.extend(quote! {
-            obj.#ident.scan_val(mem, #offset + base_addr)
+            memscanner::macro_helpers::field_value_addr(mem, base_addr, &#field)
+                .and_then(|addr| match addr {
+                    Some(addr) => obj.#ident.scan_field(mem, addr, &#field),
+                    None => Ok(()),
+                })
                 .map_err(|e| format_err!("can't read {}: {}", #ident_str, e))?;
         });
     }
@@ -48,17 +65,21 @@ fn scannable_impl(ctx: &mut Context, ast: &syn::DeriveInput) -> Option<TokenStre
     let code = quote! {
         impl memscanner::Scannable for #name {
 
+            fn type_name() -> &'static str {
+                #name_str
+            }
+
             fn get_resolver(config: memscanner::TypeConfig) -> Result<Box<memscanner::Resolver<Self>>, failure::Error> {
-                #offset_code
+                #field_code
 
                 let resolver = move |mem: &dyn memscanner::MemReader,
                                     start_addr: u64,
                                     end_addr: u64|
                     -> Result<Box<memscanner::Scanner<Self>>, failure::Error> {
                     let base_addr = config
-                        .signature
                         .resolve(mem, start_addr, end_addr)
-                        .ok_or(format_err! {"Can't resolve base address"})?;
+                        .map_err(|e| format_err!("Can't resolve base address: {}", e))?;
+                    #clone_code
 
                     let scanner = move |obj: &mut Self, mem: &dyn memscanner::MemReader| -> Result<(), failure::Error> {
                         #read_code
@@ -76,17 +97,17 @@ fn scannable_impl(ctx: &mut Context, ast: &syn::DeriveInput) -> Option<TokenStre
                     .as_ref()
                     .ok_or(format_err!("Can't create resolver for Vec<{}>: no array config.", #name_str))?.clone();
 
-                #offset_code
+                #field_code
 
                 let resolver = move |mem: &dyn memscanner::MemReader,
                                     start_addr: u64,
                                     end_addr: u64|
                     -> Result<Box<memscanner::ArrayScanner<Self>>, failure::Error> {
                     let base_addr = config
-                        .signature
                         .resolve(mem, start_addr, end_addr)
-                        .ok_or(format_err! {"Can't resolve base address"})?;
+                        .map_err(|e| format_err!("Can't resolve base address: {}", e))?;
                     let array_config = array_config.clone();
+                    #clone_code
 
                     let scanner = move |vec: &mut Vec<#name>, mem: &dyn memscanner::MemReader|
                         -> Result<(), failure::Error> {
@@ -1,7 +1,9 @@
 #[cfg(test)]
 mod tests {
     use memscanner::test::TestMemReader;
-    use memscanner::{Scannable, ScannableEnum, Signature, TypeConfig};
+    use memscanner::{
+        ConfigRegistry, ResolveCache, Scannable, ScannableEnum, Signature, TypeConfig,
+    };
 
     use failure::{format_err, Error};
     use num_derive::FromPrimitive;
@@ -45,6 +47,7 @@ mod tests {
                 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
             ],
             start_addr: 0x1000,
+            ..Default::default()
         };
         r
     }
@@ -64,6 +67,7 @@ mod tests {
                 0x73, 0x63, 0x61, 0x6E, 0x6E, 0x65, 0x72, 0x21,
             ],
             start_addr: 0x1000,
+            ..Default::default()
         };
         r
     }
@@ -83,6 +87,7 @@ mod tests {
                 0x73, 0x63, 0x61, 0x6E, 0x6E, 0x65, 0x72, 0x21,
             ],
             start_addr: 0x1000,
+            ..Default::default()
         };
         r
     }
@@ -99,6 +104,7 @@ mod tests {
                 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, // 0x1028
             ],
             start_addr: 0x1000,
+            ..Default::default()
         };
         r
     }
@@ -116,6 +122,22 @@ mod tests {
         TypeConfig::new(&mut text).unwrap()
     }
 
+    fn get_alternatives_test_type_config() -> TypeConfig {
+        let mut text = "
+        {
+            signature: [
+                [\"asm(00112244^^^^^^^^********)\"],
+                [\"asm(00112233^^^^^^^^********)\"],
+            ],
+            fields: {
+                value1: 0x0,
+                value2: 0x4,
+            }
+        }"
+        .as_bytes();
+        TypeConfig::new(&mut text).unwrap()
+    }
+
     fn get_string_test_type_config() -> TypeConfig {
         let mut text = "
         {
@@ -163,17 +185,51 @@ mod tests {
         let config = get_test_type_config();
         assert_eq!(
             config.signature,
-            Signature::new(&vec!["asm(00112233^^^^^^^^********)".to_string()]).unwrap()
+            Signature::new(&["asm(00112233^^^^^^^^********)".to_string()]).unwrap()
         );
         assert_eq!(
             config.fields,
             [("value1".to_string(), 0u64), ("value2".to_string(), 4u64)]
                 .iter()
-                .cloned()
+                .map(|(name, offset)| (name.clone(), (*offset).into()))
                 .collect()
         );
     }
 
+    #[test]
+    fn type_config_round_trip_test() -> Result<(), Error> {
+        let mut config = get_array_test_type_config();
+        config.signature = "asm(00112244^^^^^^^^) | asm(00112233^^^^^^^^); ptr(0)".parse()?;
+
+        let mut buf = Vec::new();
+        config.write(&mut buf)?;
+        let read_config = TypeConfig::new(&mut buf.as_slice())?;
+
+        assert_eq!(read_config.signature, config.signature);
+        assert_eq!(read_config.fields, config.fields);
+        let array = read_config.array.unwrap();
+        assert_eq!(array.element_size, 8);
+        assert_eq!(array.element_count, 2);
+        assert_eq!(array.uses_pointer_table, Some(true));
+
+        Ok(())
+    }
+
+    #[test]
+    fn signature_string_config_test() -> Result<(), Error> {
+        let mut text = "
+        {
+            signature: \"asm(00112233^^^^^^^^********)\",
+            fields: {
+                value1: 0x0,
+            }
+        }"
+        .as_bytes();
+        let config = TypeConfig::new(&mut text)?;
+        assert_eq!(config.signature, get_test_type_config().signature);
+        Ok(())
+    }
+
     #[test]
     fn object_test() -> Result<(), Error> {
         let config = get_test_type_config();
@@ -190,6 +246,197 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn alternatives_test() -> Result<(), Error> {
+        let config = get_alternatives_test_type_config();
+        let mem = get_test_mem_reader();
+
+        assert_eq!(
+            config.signature.resolve_alternative(
+                &mem,
+                mem.start_addr,
+                mem.start_addr + mem.mem.len() as u64
+            ),
+            Ok((1, 0x1010))
+        );
+
+        let resolver = TestObject::get_resolver(config)?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+
+        let mut obj: TestObject = Default::default();
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.value1, 0x88);
+        assert_eq!(obj.value2, 0xffeeddcc);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_test() -> Result<(), Error> {
+        let cache = ResolveCache::default().shared();
+        let config = get_test_type_config().with_cache(cache.clone());
+        let mut mem = get_test_mem_reader();
+        let end_addr = mem.start_addr + mem.mem.len() as u64;
+
+        let resolver = TestObject::get_resolver(config.clone())?;
+        let _scanner = resolver(&mem, mem.start_addr, end_addr)?;
+        assert!(cache.lock().unwrap().is_dirty());
+
+        let mut buf = Vec::new();
+        cache.lock().unwrap().write(&mut buf)?;
+
+        // A fresh run on the same module reuses the stored address.
+        let cache = ResolveCache::new(&mut buf.as_slice())?.shared();
+        let resolver = TestObject::get_resolver(config.clone().with_cache(cache.clone()))?;
+        let scanner = resolver(&mem, mem.start_addr, end_addr)?;
+        assert!(!cache.lock().unwrap().is_dirty());
+
+        let mut obj: TestObject = Default::default();
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.value1, 0x88);
+        assert_eq!(obj.value2, 0xffeeddcc);
+
+        // A changed module invalidates it.
+        mem.mem[0] = 0x00;
+        let cache = ResolveCache::new(&mut buf.as_slice())?.shared();
+        let resolver = TestObject::get_resolver(config.with_cache(cache.clone()))?;
+        let _scanner = resolver(&mem, mem.start_addr, end_addr)?;
+        assert!(cache.lock().unwrap().is_dirty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn typed_fields_test() -> Result<(), Error> {
+        let mut text = "
+        {
+            signature: [\"asm(00112233^^^^^^^^********)\"],
+            fields: {
+                value1: { offset: 0x0, type: \"u8\", size: 1 },
+                value2: { offset: 0x4, type: \"u32\" },
+            }
+        }"
+        .as_bytes();
+        let config = TypeConfig::new(&mut text)?;
+        let mem = get_test_mem_reader();
+
+        let resolver = TestObject::get_resolver(config)?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+
+        let mut obj: TestObject = Default::default();
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.value1, 0x88);
+        assert_eq!(obj.value2, 0xffeeddcc);
+
+        Ok(())
+    }
+
+    #[test]
+    fn typed_fields_mismatch_test() {
+        let error = |fields: &str| {
+            let text = format!(
+                "{{ signature: [\"asm(00112233^^^^^^^^********)\"], fields: {{ {} }} }}",
+                fields
+            );
+            let config = TypeConfig::new(&mut text.as_bytes()).unwrap();
+            TestObject::get_resolver(config)
+                .err()
+                .map(|e| e.to_string())
+        };
+
+        assert_eq!(
+            error("value1: 0x0, value2: { offset: 0x4, type: \"f32\" }"),
+            Some("value2 is declared as f32 but is u32".to_string())
+        );
+        assert_eq!(
+            error("value1: { offset: 0x0, size: 2 }, value2: 0x4"),
+            Some("value1 is declared as 2 bytes but is 1".to_string())
+        );
+        assert_eq!(
+            error("value1: 0x0, value2: { offset: 0x4, type: \"u32\", count: 2 }"),
+            Some("value2 is an array or struct, which can only be read as a DynValue".to_string())
+        );
+    }
+
+    #[test]
+    fn pointer_field_test() -> Result<(), Error> {
+        let mut text = "
+        {
+            signature: [\"asm(00112233^^^^^^^^)\"],
+            fields: {
+                value1: { offset: 0x8, type: \"u8\", ptr: true },
+                value2: { offset: 0x0, ptr: true },
+            }
+        }"
+        .as_bytes();
+        let config = TypeConfig::new(&mut text)?;
+        let mut mem = get_array_test_mem_reader();
+
+        let resolver = TestObject::get_resolver(config)?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+
+        let mut obj = TestObject {
+            value1: 0x1,
+            value2: 0x2,
+        };
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.value1, 0x00);
+        assert_eq!(obj.value2, 0xbbaa9988);
+
+        // A null pointer leaves the field alone.
+        mem.mem[0x18..0x20].copy_from_slice(&[0x0; 8]);
+        obj.value1 = 0x1;
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.value1, 0x1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn registry_test() -> Result<(), Error> {
+        let mut text = "
+        {
+            TestObject: {
+                signature: [\"asm(00112233^^^^^^^^********)\"],
+                fields: {
+                    value1: 0x0,
+                    value2: 0x4,
+                }
+            },
+            StringTestObject: {
+                signature: [\"asm(00112233^^^^^^^^********)\"],
+                fields: {
+                    s: 0x0,
+                }
+            },
+        }"
+        .as_bytes();
+        let registry = ConfigRegistry::new(&mut text)?;
+        let mem = get_test_mem_reader();
+        assert_eq!(TestObject::type_name(), "TestObject");
+
+        let resolver = registry.get_resolver_by_name::<TestObject>()?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+
+        let mut obj: TestObject = Default::default();
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.value1, 0x88);
+        assert_eq!(obj.value2, 0xffeeddcc);
+
+        assert_eq!(
+            registry
+                .get_resolver_by_name::<EnumTestObject>()
+                .err()
+                .map(|e| e.to_string()),
+            Some(
+                "no config for type EnumTestObject; known types: StringTestObject, TestObject"
+                    .to_string()
+            )
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn string_test() -> Result<(), Error> {
         let config = get_string_test_type_config();
@@ -221,6 +468,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn string_size_test() -> Result<(), Error> {
+        let mut text = "
+        {
+            signature: [\"asm(00112233^^^^^^^^********)\"],
+            fields: {
+                s: { offset: 0x0, type: \"string\", size: 10 },
+            }
+        }"
+        .as_bytes();
+        let config = TypeConfig::new(&mut text)?;
+        let mem = get_string_test_mem_reader();
+
+        let resolver = StringTestObject::get_resolver(config)?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+
+        let mut obj: StringTestObject = Default::default();
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.s, "Memscanner");
+
+        Ok(())
+    }
+
     #[test]
     fn array_test() -> Result<(), Error> {
         let config = get_array_test_type_config();
@@ -253,6 +523,21 @@ mod tests {
         scanner(&mut obj, &mem)?;
         assert_eq!(obj.e, TestEnum::Value88);
 
+        // Enums have no `FieldType`, so their size isn't checked.
+        let mut text = "
+        {
+            signature: [\"asm(00112233^^^^^^^^********)\"],
+            fields: {
+                e: { offset: 0x0, size: 1 },
+            }
+        }"
+        .as_bytes();
+        let resolver = EnumTestObject::get_resolver(TypeConfig::new(&mut text)?)?;
+        let scanner = resolver(&mem, mem.start_addr, mem.start_addr + mem.mem.len() as u64)?;
+        let mut obj: EnumTestObject = Default::default();
+        scanner(&mut obj, &mem)?;
+        assert_eq!(obj.e, TestEnum::Value88);
+
         Ok(())
     }
 }